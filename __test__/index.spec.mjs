@@ -1,7 +1,100 @@
 import test from 'ava'
 
-import { sum } from '../index.js'
+import { mkdirSync, mkdtempSync, realpathSync, rmSync, writeFileSync } from 'fs'
+import { tmpdir } from 'os'
+import { dirname, join } from 'path'
 
-test('sum from native', (t) => {
-  t.is(sum(1, 2), 3)
+import { indexUsages, mightContainWalnut, resolveDependencies, resolveVariants, walnutParse } from '../index.mjs'
+
+// Writes `files` into a fresh directory, removed again once the test is done.
+function fixture(t, files) {
+  const root = realpathSync(mkdtempSync(join(tmpdir(), 'walnut-spec-')))
+  for (const [name, code] of Object.entries(files)) {
+    mkdirSync(dirname(join(root, name)), { recursive: true })
+    writeFileSync(join(root, name), code)
+  }
+  t.teardown(() => rmSync(root, { recursive: true, force: true }))
+  return root
+}
+
+test('files without walnut are returned untouched', (t) => {
+  const code = 'export const a = 1;\n'
+  t.false(mightContainWalnut(code))
+  t.is(walnutParse(code, 'plain.ts', 'ios'), code)
+})
+
+test('the text check knows configured packages', (t) => {
+  t.true(mightContainWalnut('import x from "@app/variants";', { packages: ['@app/variants'] }))
+  t.false(mightContainWalnut('import x from "walnut-ts";', { packages: ['@app/variants'] }))
+})
+
+test('$Val picks the value of the active key', (t) => {
+  const code = 'import { $Val } from "walnut-ts";\nexport const size = $Val({ ios: 1, web: 2 });\n'
+  const output = walnutParse(code, 'size.ts', 'web')
+  t.regex(output, /size = 2/)
+  t.notRegex(output, /walnut-ts/)
+})
+
+test('resolvers are satisfied through the resolver function', (t) => {
+  const code = 'import { $Resolve } from "walnut-ts";\nimport { theme } from "./res";\nexport const value = $Resolve(theme);\n'
+  const root = fixture(t, {
+    'res.ts': 'import { makeResolver } from "walnut-ts";\nexport const theme = makeResolver("theme");\n',
+    'main.ts': code,
+  })
+  const labels = []
+  const output = walnutParse(code, join(root, 'main.ts'), 'ios', (label) => {
+    labels.push(label)
+    return '"dark"'
+  })
+
+  t.deepEqual(labels, ['theme'])
+  t.regex(output, /value = "dark"/)
+  t.throws(() => walnutParse(code, join(root, 'main.ts'), 'ios'), { message: /no resolver function/ })
+})
+
+test('errors are thrown with their position', (t) => {
+  const code = 'import { $Val } from "walnut-ts";\nconsole.log($Val);\n'
+  t.throws(() => walnutParse(code, 'broken.ts', 'ios'), { message: /broken\.ts:2:13: `\$Val` is used here/ })
+  t.throws(() => walnutParse('import { $Val } from "walnut-ts";\nconst = 1;\n', 'syntax.ts', 'ios'), {
+    message: /syntax\.ts:2:\d+: Syntax error/,
+  })
+})
+
+test('dependency graphs list edges and cycles', (t) => {
+  const root = fixture(t, {
+    'a.ts': 'import { b } from "./b";\nexport const a = () => b;\n',
+    'b.ts': 'import { a } from "./a";\nexport const b = () => a;\n',
+  })
+  const graph = resolveDependencies(root, join(root, 'a.ts'))
+
+  t.deepEqual(
+    graph.getNodes().map((n) => n.path).sort(),
+    [join(root, 'a.ts'), join(root, 'b.ts')],
+  )
+  t.deepEqual(graph.getCycles(), [[join(root, 'a.ts'), join(root, 'b.ts'), join(root, 'a.ts')]])
+})
+
+test('variants only ship the modules their key uses', (t) => {
+  const root = fixture(t, {
+    'app.ts':
+      'import { $Val } from "walnut-ts";\nimport { ios } from "./ios";\nimport { web } from "./web";\nexport const platform = $Val({ ios: ios, web: web });\n',
+    'ios.ts': 'export const ios = "ios";\n',
+    'web.ts': 'export const web = "web";\n',
+  })
+  const report = resolveVariants(root, join(root, 'app.ts'), ['ios'])
+
+  t.deepEqual(report.variants[0].modules.sort(), [join(root, 'app.ts'), join(root, 'ios.ts')])
+  t.deepEqual(report.dead, [join(root, 'web.ts')])
+})
+
+test('usages are indexed by file and key', (t) => {
+  const root = fixture(t, {
+    'main.ts': 'import { title } from "./title";\nexport const all = [title];\n',
+    'title.ts': 'import { $Val } from "walnut-ts";\nexport const title = $Val({ ios: "iOS", web: "Web" });\n',
+  })
+  const index = indexUsages(root, join(root, 'main.ts'))
+
+  t.deepEqual(index.getErrors(), [])
+  t.deepEqual(index.getKeysByFile(), { [join(root, 'title.ts')]: ['ios', 'web'] })
+  t.deepEqual(index.getFilesByKey(), { ios: [join(root, 'title.ts')], web: [join(root, 'title.ts')] })
 })
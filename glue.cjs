@@ -295,8 +295,14 @@ if (!nativeBinding) {
   throw new Error(`Failed to load native binding`)
 }
 
-const { WalnutHandler, getHandler, resolveDependencies } = nativeBinding
+const { DependencyGraph, UsageIndex, WalnutHandler, getHandler, mightContainWalnut, resolveDependencies, resolveVariants, indexUsages, invalidateResolverCache } = nativeBinding
 
+module.exports.DependencyGraph = DependencyGraph
+module.exports.UsageIndex = UsageIndex
 module.exports.WalnutHandler = WalnutHandler
 module.exports.getHandler = getHandler
+module.exports.mightContainWalnut = mightContainWalnut
 module.exports.resolveDependencies = resolveDependencies
+module.exports.resolveVariants = resolveVariants
+module.exports.indexUsages = indexUsages
+module.exports.invalidateResolverCache = invalidateResolverCache
@@ -3,11 +3,87 @@
 
 /* auto-generated by NAPI-RS */
 
-export function getHandler(code: string, id: string, walnutKey: string): WalnutHandler
-export function resolveDependencies(base: string, entry: string): void
+export interface WalnutDiagnostic {
+  severity: string
+  message: string
+  file: string
+  line: number
+  column: number
+}
+export interface DependencyOptions {
+  includeNodeModules?: boolean
+  walnutKey?: string
+}
+export interface DependencyNode {
+  path: string
+  kind: string
+  containsWalnut: boolean
+  isEntry: boolean
+  error?: string
+}
+export interface DependencyEdge {
+  from: string
+  to?: string
+  specifier: string
+  kind: string
+  typeOnly: boolean
+  error?: string
+}
+export interface VariantModules {
+  key: string
+  modules: Array<string>
+}
+export interface VariantReport {
+  variants: Array<VariantModules>
+  dead: Array<string>
+}
+export interface WalnutUsage {
+  file: string
+  line: number
+  column: number
+  kind: string
+  keys: Array<string>
+  fallback: boolean
+  resolver?: string
+}
+export interface WalnutOptions {
+  jsxNames?: Array<string>
+  packages?: Array<string>
+  baseUrl?: string
+  paths?: Record<string, Array<string>>
+}
+export function getHandler(code: string, id: string, walnutKey: string, options?: WalnutOptions | undefined | null): WalnutHandler
+export function mightContainWalnut(code: string, options?: WalnutOptions | undefined | null): boolean
+export function resolveDependencies(base: string, entries: string | Array<string>, options?: WalnutOptions | undefined | null, graphOptions?: DependencyOptions | undefined | null): DependencyGraph
+export function resolveVariants(base: string, entries: string | Array<string>, keys: Array<string>, options?: WalnutOptions | undefined | null, graphOptions?: DependencyOptions | undefined | null): VariantReport
+export function indexUsages(base: string, entries: string | Array<string>, options?: WalnutOptions | undefined | null, graphOptions?: DependencyOptions | undefined | null): UsageIndex
+export function invalidateResolverCache(path?: string | undefined | null): void
+export class DependencyGraph {
+  getNodes(): Array<DependencyNode>
+  getEdges(): Array<DependencyEdge>
+  /**
+   * Import cycles as the paths along them, starting and ending with the same module.
+   * Every module that is part of a cycle is on at least one of them, but not every distinct
+   * cycle through it is listed.
+   */
+  getCycles(): Array<Array<string>>
+  toJson(): string
+  toDot(): string
+}
+export class UsageIndex {
+  getUsages(): Array<WalnutUsage>
+  getErrors(): Array<WalnutDiagnostic>
+  getKeysByFile(): Record<string, Array<string>>
+  getFilesByKey(): Record<string, Array<string>>
+  lackingKey(key: string): Array<WalnutUsage>
+  getSingleUseKeys(): Array<string>
+  toJson(): string
+}
 export class WalnutHandler {
   needResolver: boolean
   run(): void
+  getDiagnostics(): Array<WalnutDiagnostic>
+  getWalnutSources(): Array<string>
   getOutput(): string
   getResolverLabels(): Array<string>
   satisfyResolvers(resolverArr: Array<[string, string]>): void
@@ -1,4 +1,7 @@
-declare function walnutParse(code: string, id: string, walnutKey: string, resolverFn?: ResolverFn): string;
+import { WalnutOptions } from './glue.cjs';
+export { DependencyEdge, DependencyGraph, DependencyNode, DependencyOptions, indexUsages, invalidateResolverCache, mightContainWalnut, resolveDependencies, resolveVariants, UsageIndex, VariantModules, VariantReport, WalnutDiagnostic, WalnutOptions, WalnutUsage } from './glue.cjs';
+
+declare function walnutParse(code: string, id: string, walnutKey: string, resolverFn?: ResolverFn, options?: WalnutOptions): string;
 type ResolverFn = (label: string) => string;
 
 export { walnutParse };
@@ -1,8 +1,12 @@
-import { getHandler } from './glue.cjs';
+import { getHandler, indexUsages, invalidateResolverCache, mightContainWalnut, resolveDependencies, resolveVariants } from './glue.cjs';
 
-function walnutParse(code, id, walnutKey, resolverFn) {
-  const handler = getHandler(code, id, walnutKey);
+function walnutParse(code, id, walnutKey, resolverFn, options) {
+  if (!mightContainWalnut(code, options)) {
+    return code;
+  }
+  const handler = getHandler(code, id, walnutKey, options);
   handler.run();
+  reportDiagnostics(handler.getDiagnostics());
   if (handler.needResolver) {
     if (!resolverFn) {
       throw new Error(
@@ -20,4 +24,15 @@ function walnutParse(code, id, walnutKey, resolverFn) {
   return handler.getOutput();
 }
 
-export { walnutParse };
+function reportDiagnostics(diagnostics) {
+  const format = (d) => `${d.file}:${d.line}:${d.column}: ${d.message}`;
+  diagnostics.filter((d) => d.severity === "warning").forEach((d) => console.warn(`[walnut] ${format(d)}`));
+  const errors = diagnostics.filter((d) => d.severity === "error");
+  if (errors.length > 0) {
+    throw new Error(
+      `Walnut failed to transform ${errors[0].file}:\n` + errors.map(format).join("\n")
+    );
+  }
+}
+
+export { indexUsages, invalidateResolverCache, mightContainWalnut, resolveDependencies, resolveVariants, walnutParse };
@@ -1,13 +1,38 @@
-import { getHandler } from "./glue.cjs";
+import {
+  getHandler,
+  indexUsages,
+  invalidateResolverCache,
+  mightContainWalnut,
+  resolveDependencies,
+  resolveVariants,
+} from "./glue.cjs";
+import type {
+  DependencyEdge,
+  DependencyGraph,
+  DependencyNode,
+  DependencyOptions,
+  UsageIndex,
+  VariantModules,
+  VariantReport,
+  WalnutDiagnostic,
+  WalnutOptions,
+  WalnutUsage,
+} from "./glue.cjs";
 
 export function walnutParse(
   code: string,
   id: string,
   walnutKey: string,
-  resolverFn?: ResolverFn
+  resolverFn?: ResolverFn,
+  options?: WalnutOptions
 ): string {
-  const handler = getHandler(code, id, walnutKey);
+  if (!mightContainWalnut(code, options)) {
+    return code;
+  }
+
+  const handler = getHandler(code, id, walnutKey, options);
   handler.run();
+  reportDiagnostics(handler.getDiagnostics());
   if (handler.needResolver) {
     if (!resolverFn) {
       throw new Error(
@@ -29,4 +54,41 @@ export function walnutParse(
   return handler.getOutput();
 }
 
+function reportDiagnostics(diagnostics: WalnutDiagnostic[]) {
+  const format = (d: WalnutDiagnostic) =>
+    `${d.file}:${d.line}:${d.column}: ${d.message}`;
+
+  diagnostics
+    .filter((d) => d.severity === "warning")
+    .forEach((d) => console.warn(`[walnut] ${format(d)}`));
+
+  const errors = diagnostics.filter((d) => d.severity === "error");
+  if (errors.length > 0) {
+    throw new Error(
+      `Walnut failed to transform ${errors[0].file}:\n` +
+        errors.map(format).join("\n")
+    );
+  }
+}
+
 export type ResolverFn = (label: string) => string;
+
+export {
+  indexUsages,
+  invalidateResolverCache,
+  mightContainWalnut,
+  resolveDependencies,
+  resolveVariants,
+};
+export type {
+  DependencyEdge,
+  DependencyGraph,
+  DependencyNode,
+  DependencyOptions,
+  UsageIndex,
+  VariantModules,
+  VariantReport,
+  WalnutDiagnostic,
+  WalnutOptions,
+  WalnutUsage,
+};
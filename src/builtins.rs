@@ -0,0 +1,150 @@
+#![deny(clippy::all)]
+
+use std::{ env, fs, path::{ Path, PathBuf }, sync::OnceLock, time::{ SystemTime, UNIX_EPOCH } };
+
+/*
+    Resolvers that are satisfied in Rust without a round-trip to the JS resolver function.
+    They're picked by label prefix, e.g. `Walnut.makeResolver("env:API_URL")` or
+    `Walnut.builtin("env", "API_URL")`:
+
+        env:NAME          the environment variable NAME as a string, or undefined
+        git:commit        the commit checked out in the repository containing the module
+        build:timestamp   milliseconds since the epoch when the build started
+        file:PATH         the contents of PATH, relative to the module, as a string
+        json:PATH         the JSON in PATH, relative to the module, inlined as a value
+        pkg:FIELD         FIELD of the nearest package.json, e.g. pkg:version
+
+    The module is the one declaring the resolver, so a shared resolver reads the same file for
+    everyone using it. Every value is returned as JS source, the same as a resolver function would.
+*/
+static BUILD_TIMESTAMP: OnceLock<u128> = OnceLock::new();
+
+/*
+    Resolves a builtin label declared in the module at `module_path`.
+    Returns None if the label isn't a builtin at all.
+*/
+pub(crate) fn resolve_builtin(label: &str, module_path: &str) -> Option<Result<String, String>> {
+    let (prefix, arg) = label.split_once(':')?;
+    let module_dir = Path::new(module_path).parent().unwrap_or(Path::new("."));
+
+    let res = match prefix {
+        "env" =>
+            Ok(match env::var(arg) {
+                Ok(v) => js_string(&v),
+                Err(_) => String::from("undefined"),
+            }),
+        "git" =>
+            match arg {
+                "commit" => git_commit(module_dir).map(|c| js_string(&c)),
+                _ => Err(format!("unknown git builtin `{arg}`, expected git:commit")),
+            }
+        "build" =>
+            match arg {
+                "timestamp" => {
+                    let ts = BUILD_TIMESTAMP.get_or_init(|| {
+                        SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .map(|d| d.as_millis())
+                            .unwrap_or(0)
+                    });
+                    Ok(ts.to_string())
+                }
+                _ => Err(format!("unknown build builtin `{arg}`, expected build:timestamp")),
+            }
+        "file" => read_relative(module_dir, arg).map(|s| js_string(&s)),
+        "json" =>
+            read_relative(module_dir, arg).and_then(|s| {
+                match serde_json::from_str::<serde_json::Value>(&s) {
+                    Ok(v) => Ok(format!("({v})")),
+                    Err(e) => Err(format!("{arg} is not valid JSON: {e}")),
+                }
+            }),
+        "pkg" => package_field(module_dir, arg),
+        _ => {
+            return None;
+        }
+    };
+
+    Some(res)
+}
+
+fn js_string(s: &str) -> String {
+    serde_json::Value::String(String::from(s)).to_string()
+}
+
+fn read_relative(module_dir: &Path, path: &str) -> Result<String, String> {
+    let full_path = module_dir.join(path);
+    fs::read_to_string(&full_path).map_err(|e| format!("failed to read {}: {e}", full_path.display()))
+}
+
+fn package_field(module_dir: &Path, field: &str) -> Result<String, String> {
+    let Some(pkg_path) = find_up(module_dir, "package.json") else {
+        return Err(format!("no package.json found above {}", module_dir.display()));
+    };
+
+    let pkg = fs::read_to_string(&pkg_path).map_err(|e| format!("failed to read {}: {e}", pkg_path.display()))?;
+    let pkg: serde_json::Value = serde_json::from_str(&pkg).map_err(|e| {
+        format!("{} is not valid JSON: {e}", pkg_path.display())
+    })?;
+
+    match pkg.get(field) {
+        Some(v) => Ok(v.to_string()),
+        None => Err(format!("{} has no `{field}` field", pkg_path.display())),
+    }
+}
+
+fn find_up(dir: &Path, name: &str) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|d| d.join(name))
+        .find(|p| p.exists())
+}
+
+fn git_commit(module_dir: &Path) -> Result<String, String> {
+    let Some(dot_git) = find_up(module_dir, ".git") else {
+        return Err(format!("no git repository found above {}", module_dir.display()));
+    };
+
+    // Worktrees and submodules have a `.git` file pointing at the real git dir.
+    let git_dir = if dot_git.is_file() {
+        let contents = fs::read_to_string(&dot_git).map_err(|e| e.to_string())?;
+        let Some(dir) = contents.trim().strip_prefix("gitdir:") else {
+            return Err(format!("{} is not a valid .git file", dot_git.display()));
+        };
+        dot_git.parent().unwrap_or(Path::new(".")).join(dir.trim())
+    } else {
+        dot_git
+    };
+
+    let head_path = git_dir.join("HEAD");
+    let head = fs::read_to_string(&head_path).map_err(|e| format!("failed to read {}: {e}", head_path.display()))?;
+    let head = head.trim();
+
+    let Some(ref_name) = head.strip_prefix("ref:").map(|r| r.trim()) else {
+        // Detached HEAD holds the commit itself.
+        return Ok(String::from(head));
+    };
+
+    // Refs may live in the common dir of a worktree, loose or packed.
+    let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+        Ok(c) => git_dir.join(c.trim()),
+        Err(_) => git_dir.clone(),
+    };
+
+    for dir in [&git_dir, &common_dir] {
+        if let Ok(commit) = fs::read_to_string(dir.join(ref_name)) {
+            return Ok(String::from(commit.trim()));
+        }
+
+        if let Ok(packed) = fs::read_to_string(dir.join("packed-refs")) {
+            let found = packed.lines().find_map(|line| {
+                let (commit, name) = line.split_once(' ')?;
+                (name == ref_name).then(|| String::from(commit))
+            });
+            if let Some(commit) = found {
+                return Ok(commit);
+            }
+        }
+    }
+
+    Err(format!("unable to find the commit for {ref_name}"))
+}
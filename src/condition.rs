@@ -0,0 +1,260 @@
+#![deny(clippy::all)]
+
+/*
+    A boolean expression over walnut keys, as written in `<$Walnut when="ios && !tablet">`.
+    Supports `&&`, `||`, `!` and parentheses; anything else is a key name.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Condition {
+    Key(String),
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+impl Condition {
+    pub fn parse(src: &str) -> Result<Condition, String> {
+        let tokens = tokenize(src)?;
+        let mut parser = ConditionParser { tokens, pos: 0 };
+
+        let cond = parser.parse_or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(cond),
+            Some(t) => Err(format!("unexpected `{}` in \"{src}\"", t.text())),
+        }
+    }
+
+    // Every key the condition mentions, in order of first mention.
+    pub fn keys(&self) -> Vec<String> {
+        fn collect(cond: &Condition, keys: &mut Vec<String>) {
+            match cond {
+                Condition::Key(k) => {
+                    if !keys.contains(k) {
+                        keys.push(k.clone());
+                    }
+                }
+                Condition::Not(c) => collect(c, keys),
+                Condition::And(a, b) | Condition::Or(a, b) => {
+                    collect(a, keys);
+                    collect(b, keys);
+                }
+            }
+        }
+
+        let mut keys = Vec::new();
+        collect(self, &mut keys);
+        keys
+    }
+
+    pub fn eval(&self, active_keys: &[String]) -> bool {
+        match self {
+            Condition::Key(k) => active_keys.iter().any(|a| a == k),
+            Condition::Not(c) => !c.eval(active_keys),
+            Condition::And(a, b) => a.eval(active_keys) && b.eval(active_keys),
+            Condition::Or(a, b) => a.eval(active_keys) || b.eval(active_keys),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Key(String),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl Token {
+    fn text(&self) -> &str {
+        match self {
+            Token::Key(k) => k,
+            Token::And => "&&",
+            Token::Or => "||",
+            Token::Not => "!",
+            Token::Open => "(",
+            Token::Close => ")",
+        }
+    }
+}
+
+fn tokenize(src: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = src.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '!' => tokens.push(Token::Not),
+            '&' | '|' => {
+                if chars.next() != Some(c) {
+                    return Err(format!("expected `{c}{c}` in \"{src}\""));
+                }
+                tokens.push(if c == '&' { Token::And } else { Token::Or });
+            }
+            c => {
+                let mut key = String::from(c);
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || "()!&|".contains(next) {
+                        break;
+                    }
+                    key.push(next);
+                    chars.next();
+                }
+                tokens.push(Token::Key(key));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct ConditionParser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl ConditionParser {
+    fn next(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.tokens.get(self.pos) == Some(token) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn parse_or(&mut self) -> Result<Condition, String> {
+        let mut cond = self.parse_and()?;
+        while self.eat(&Token::Or) {
+            cond = Condition::Or(Box::new(cond), Box::new(self.parse_and()?));
+        }
+        Ok(cond)
+    }
+
+    fn parse_and(&mut self) -> Result<Condition, String> {
+        let mut cond = self.parse_unary()?;
+        while self.eat(&Token::And) {
+            cond = Condition::And(Box::new(cond), Box::new(self.parse_unary()?));
+        }
+        Ok(cond)
+    }
+
+    fn parse_unary(&mut self) -> Result<Condition, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(Condition::Not(Box::new(self.parse_unary()?))),
+            Some(Token::Open) => {
+                let cond = self.parse_or()?;
+                if !self.eat(&Token::Close) {
+                    return Err(String::from("missing `)`"));
+                }
+                Ok(cond)
+            }
+            Some(Token::Key(k)) => Ok(Condition::Key(k)),
+            Some(t) => Err(format!("unexpected `{}`", t.text())),
+            None => Err(String::from("unexpected end of condition")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(k: &str) -> Condition {
+        Condition::Key(String::from(k))
+    }
+
+    fn keys(ks: &[&str]) -> Vec<String> {
+        ks.iter().map(|k| String::from(*k)).collect()
+    }
+
+    #[test]
+    fn parses_a_single_key() {
+        assert_eq!(Condition::parse("ios"), Ok(key("ios")));
+        assert_eq!(Condition::parse("  web-desktop  "), Ok(key("web-desktop")));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            Condition::parse("a || b && c"),
+            Ok(Condition::Or(Box::new(key("a")), Box::new(Condition::And(Box::new(key("b")), Box::new(key("c"))))))
+        );
+        assert_eq!(
+            Condition::parse("a && b || c"),
+            Ok(Condition::Or(Box::new(Condition::And(Box::new(key("a")), Box::new(key("b")))), Box::new(key("c"))))
+        );
+    }
+
+    #[test]
+    fn operators_are_left_associative() {
+        assert_eq!(
+            Condition::parse("a || b || c"),
+            Ok(Condition::Or(Box::new(Condition::Or(Box::new(key("a")), Box::new(key("b")))), Box::new(key("c"))))
+        );
+    }
+
+    #[test]
+    fn not_applies_to_the_next_operand() {
+        assert_eq!(
+            Condition::parse("!a && b"),
+            Ok(Condition::And(Box::new(Condition::Not(Box::new(key("a")))), Box::new(key("b"))))
+        );
+        assert_eq!(Condition::parse("!!a"), Ok(Condition::Not(Box::new(Condition::Not(Box::new(key("a")))))));
+    }
+
+    #[test]
+    fn parentheses_group() {
+        assert_eq!(
+            Condition::parse("(a || b) && !(c)"),
+            Ok(
+                Condition::And(
+                    Box::new(Condition::Or(Box::new(key("a")), Box::new(key("b")))),
+                    Box::new(Condition::Not(Box::new(key("c"))))
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn operators_need_no_spaces() {
+        assert_eq!(Condition::parse("a&&!b"), Condition::parse("a && !b"));
+    }
+
+    #[test]
+    fn rejects_malformed_conditions() {
+        assert!(Condition::parse("").is_err());
+        assert!(Condition::parse("a &").is_err());
+        assert!(Condition::parse("a | b").is_err());
+        assert!(Condition::parse("a &&").is_err());
+        assert!(Condition::parse("(a || b").is_err());
+        assert!(Condition::parse("a || b)").is_err());
+        assert!(Condition::parse("a b").is_err());
+        assert!(Condition::parse("&& a").is_err());
+    }
+
+    #[test]
+    fn evaluates_against_active_keys() {
+        let cond = Condition::parse("ios && !tablet || web").unwrap();
+        assert!(cond.eval(&keys(&["ios"])));
+        assert!(!cond.eval(&keys(&["ios", "tablet"])));
+        assert!(cond.eval(&keys(&["ios", "tablet", "web"])));
+        assert!(!cond.eval(&keys(&["android"])));
+        assert!(!cond.eval(&[]));
+    }
+
+    #[test]
+    fn lists_keys_once_in_order() {
+        let cond = Condition::parse("(ios || android) && !tablet || ios").unwrap();
+        assert_eq!(cond.keys(), keys(&["ios", "android", "tablet"]));
+    }
+}
@@ -0,0 +1,60 @@
+#![deny(clippy::all)]
+
+use swc_common::{ SourceMap, Span };
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Severity {
+    Error,
+    Warning,
+}
+
+/*
+    A problem found while transforming a file. Spans are turned into line/column
+    positions once the handler hands them over to JS.
+*/
+#[derive(Clone, Debug)]
+pub(crate) struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn error(message: String, span: Span) -> Self {
+        Diagnostic { severity: Severity::Error, message, span }
+    }
+
+    pub fn warning(message: String, span: Span) -> Self {
+        Diagnostic { severity: Severity::Warning, message, span }
+    }
+
+    pub fn to_js(&self, cm: &SourceMap, file: &str) -> WalnutDiagnostic {
+        let (line, column) = if self.span.is_dummy() {
+            (0, 0)
+        } else {
+            let loc = cm.lookup_char_pos(self.span.lo);
+            (loc.line as u32, (loc.col.0 as u32) + 1)
+        };
+
+        WalnutDiagnostic {
+            severity: String::from(match self.severity {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+            }),
+            message: self.message.clone(),
+            file: String::from(file),
+            line,
+            column,
+        }
+    }
+}
+
+#[napi(object)]
+#[derive(Clone, Debug)]
+pub struct WalnutDiagnostic {
+    pub severity: String,
+    pub message: String,
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+}
@@ -6,19 +6,27 @@ use swc_ecma_visit::{ VisitMut, VisitMutWith };
 use swc_common::util::take::Take;
 use swc_common::DUMMY_SP;
 
+use crate::resolver::ResolverLoc;
+use crate::options::is_walnut_package;
+
 /*
     A struct to clean up any Walnut imports and do various other things for final pass
 */
 pub(crate) struct WalnutFinalize {
     pub resolver_imports_to_remove: HashSet<String>,
-    pub resolver_locs: HashMap<String, String>,
+    pub resolver_locs: HashMap<String, ResolverLoc>,
+    local_resolvers_to_remove: HashSet<Id>,
 }
 
 impl WalnutFinalize {
-    pub fn new(resolver_imports_to_remove: HashSet<String>) -> Self {
+    pub fn new(
+        resolver_imports_to_remove: HashSet<String>,
+        local_resolvers_to_remove: HashSet<Id>
+    ) -> Self {
         WalnutFinalize {
             resolver_imports_to_remove,
             resolver_locs: HashMap::new(),
+            local_resolvers_to_remove,
         }
     }
 
@@ -45,7 +53,15 @@ impl WalnutFinalize {
                     if self.resolver_imports_to_remove.contains(&id_string) {
                         self.remove_import_id(decl, &id_string);
                         removed_something = true;
-                        self.resolver_locs.insert(id_string, String::from(&*decl.src.value));
+                        let name = match &v.imported {
+                            Some(ModuleExportName::Ident(i)) => String::from(&*i.sym),
+                            Some(ModuleExportName::Str(s)) => s.value.to_string_lossy().into_owned(),
+                            None => id_string.clone(),
+                        };
+                        self.resolver_locs.insert(id_string, ResolverLoc {
+                            src: decl.src.value.to_string_lossy().into_owned(),
+                            name,
+                        });
                     }
                 }
                 ImportSpecifier::Default(v) => {
@@ -53,7 +69,10 @@ impl WalnutFinalize {
                     if self.resolver_imports_to_remove.contains(&id_string) {
                         self.remove_import_id(decl, &id_string);
                         removed_something = true;
-                        self.resolver_locs.insert(id_string, String::from(&*decl.src.value));
+                        self.resolver_locs.insert(id_string, ResolverLoc {
+                            src: decl.src.value.to_string_lossy().into_owned(),
+                            name: String::from("default"),
+                        });
                     }
                 }
                 ImportSpecifier::Namespace(v) => {
@@ -61,7 +80,10 @@ impl WalnutFinalize {
                     if self.resolver_imports_to_remove.contains(&id_string) {
                         self.remove_import_id(decl, &id_string);
                         removed_something = true;
-                        self.resolver_locs.insert(id_string, String::from(&*decl.src.value));
+                        self.resolver_locs.insert(id_string.clone(), ResolverLoc {
+                            src: decl.src.value.to_string_lossy().into_owned(),
+                            name: id_string,
+                        });
                     }
                 }
             }
@@ -85,8 +107,11 @@ impl VisitMut for WalnutFinalize {
         n.visit_mut_children_with(self);
 
         n.retain(|node| {
-            if node.name.is_invalid() {
-                return false;
+            // Same-file resolvers that were only there for $Resolve.
+            if let Pat::Ident(s) = &node.name {
+                if self.local_resolvers_to_remove.contains(&s.to_id()) {
+                    return false;
+                }
             }
             true
         })
@@ -95,13 +120,10 @@ impl VisitMut for WalnutFinalize {
     fn visit_mut_stmt(&mut self, n: &mut Stmt) {
         n.visit_mut_children_with(self);
 
-        match n {
-            Stmt::Decl(Decl::Var(var)) => {
-                if var.decls.is_empty() {
-                    *n = Stmt::Empty(EmptyStmt { span: DUMMY_SP });
-                }
+        if let Stmt::Decl(Decl::Var(var)) = n {
+            if var.decls.is_empty() {
+                *n = Stmt::Empty(EmptyStmt { span: DUMMY_SP });
             }
-            _ => {}
         }
     }
 
@@ -123,3 +145,88 @@ impl VisitMut for WalnutFinalize {
         });
     }
 }
+
+/*
+    Drops walnut imports and requires once nothing refers to them anymore. This runs after everything
+    else so a usage that couldn't be transformed keeps its import around instead of dangling.
+*/
+pub(crate) struct UnusedWalnutRemover {
+    unused: HashSet<Id>,
+    packages: Vec<String>,
+}
+
+impl UnusedWalnutRemover {
+    pub fn new(unused: HashSet<Id>, packages: Vec<String>) -> Self {
+        UnusedWalnutRemover { unused, packages }
+    }
+
+    fn is_unused(&self, spec: &ImportSpecifier) -> bool {
+        let local = match spec {
+            ImportSpecifier::Named(v) => &v.local,
+            ImportSpecifier::Default(v) => &v.local,
+            ImportSpecifier::Namespace(v) => &v.local,
+        };
+        self.unused.contains(&local.to_id())
+    }
+}
+
+impl VisitMut for UnusedWalnutRemover {
+    fn visit_mut_import_decl(&mut self, n: &mut ImportDecl) {
+        // `import "walnut-ts"` has nothing to keep around.
+        if n.specifiers.is_empty() {
+            if is_walnut_package(&n.src.value.to_string_lossy(), &self.packages) {
+                n.take();
+            }
+            return;
+        }
+
+        let before = n.specifiers.len();
+        n.specifiers.retain(|spec| !self.is_unused(spec));
+
+        if n.specifiers.is_empty() && before > 0 {
+            n.take();
+        }
+    }
+
+    fn visit_mut_var_declarators(&mut self, n: &mut Vec<VarDeclarator>) {
+        n.visit_mut_children_with(self);
+
+        n.retain_mut(|node| {
+            match &mut node.name {
+                Pat::Ident(s) => !self.unused.contains(&s.to_id()),
+                // `const { $Val, $Resolve } = require("walnut-ts")`
+                Pat::Object(obj) if !obj.props.is_empty() => {
+                    obj.props.retain(|prop| {
+                        match prop {
+                            ObjectPatProp::KeyValue(KeyValuePatProp { value, .. }) =>
+                                !matches!(&**value, Pat::Ident(local) if self.unused.contains(&local.to_id())),
+                            ObjectPatProp::Assign(AssignPatProp { key, value: None, .. }) =>
+                                !self.unused.contains(&key.to_id()),
+                            _ => true,
+                        }
+                    });
+                    !obj.props.is_empty()
+                }
+                _ => true,
+            }
+        })
+    }
+
+    fn visit_mut_stmts(&mut self, n: &mut Vec<Stmt>) {
+        n.visit_mut_children_with(self);
+
+        n.retain(|s| !matches!(s, Stmt::Decl(Decl::Var(var)) if var.decls.is_empty()));
+    }
+
+    fn visit_mut_module_items(&mut self, n: &mut Vec<ModuleItem>) {
+        n.visit_mut_children_with(self);
+
+        n.retain(|s| {
+            match s {
+                ModuleItem::ModuleDecl(ModuleDecl::Import(x)) => !x.src.is_empty(),
+                ModuleItem::Stmt(Stmt::Decl(Decl::Var(var))) => !var.decls.is_empty(),
+                _ => true,
+            }
+        });
+    }
+}
@@ -0,0 +1,37 @@
+#![deny(clippy::all)]
+
+use std::{ fs, path::{ Path, PathBuf } };
+
+/*
+    A throwaway project under the temp dir for tests, removed again on drop.
+    `files` are (path relative to the root, contents) pairs.
+*/
+pub(crate) struct Fixture(PathBuf);
+
+impl Fixture {
+    pub fn new(name: &str, files: &[(&str, &str)]) -> Self {
+        let root = std::env::temp_dir().join(format!("walnut-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (file, contents) in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, contents).unwrap();
+        }
+        Fixture(root.canonicalize().unwrap())
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.0
+    }
+
+    // Absolute path of a file in the fixture.
+    pub fn path(&self, file: &str) -> String {
+        self.0.join(file).to_string_lossy().into_owned()
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
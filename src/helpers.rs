@@ -1,38 +1,35 @@
 #![deny(clippy::all)]
 
+use std::collections::{ HashMap, HashSet };
+use swc_common::Span;
 use swc_ecma_ast::*;
-use swc_ecma_visit::Visit;
+use swc_ecma_visit::{ Visit, VisitWith };
 
-use crate::transform::WalnutSymbols;
+use crate::transform::{ intrinsic_of, Intrinsic, WalnutBindings };
+use crate::options::is_walnut_package;
 
 /*
     A helper struct to find a usage of a Walnut Function undeneath a variable declaration.
 */
-pub(crate) struct WalnutFinder {
+pub(crate) struct WalnutFinder<'a> {
+  bindings: &'a WalnutBindings,
   pub res: Option<CallExpr>,
 }
 
-impl WalnutFinder {
-  pub fn new() -> Self {
-      WalnutFinder { res: None }
+impl<'a> WalnutFinder<'a> {
+  pub fn new(bindings: &'a WalnutBindings) -> Self {
+      WalnutFinder { bindings, res: None }
   }
 
-  fn is_valid_identifier(e: &Expr) -> bool {
-      match e {
-          Expr::Ident(i) =>
-              match &*i.sym {
-                  WalnutSymbols::VAL | WalnutSymbols::PVAL | WalnutSymbols::RESOLVE => true,
-                  _ => false,
-              }
-          _ => false,
-      }
+  fn is_valid_identifier(&self, e: &Expr) -> bool {
+      matches!(intrinsic_of(self.bindings, e), Some(Intrinsic::Val | Intrinsic::PVal | Intrinsic::Resolve))
   }
 }
 
-impl Visit for WalnutFinder {
+impl<'a> Visit for WalnutFinder<'a> {
   fn visit_call_expr(&mut self, n: &CallExpr) {
       match &n.callee {
-          Callee::Expr(callee) if Self::is_valid_identifier(callee) => {}
+          Callee::Expr(callee) if self.is_valid_identifier(callee) => {}
           _ => {
               return;
           }
@@ -40,6 +37,13 @@ impl Visit for WalnutFinder {
 
       self.res = Some(n.clone());
   }
+
+  // Calls inside nested functions belong to their own declarations.
+  fn visit_function(&mut self, _: &Function) {}
+
+  fn visit_arrow_expr(&mut self, _: &ArrowExpr) {}
+
+  fn visit_class(&mut self, _: &Class) {}
 }
 
 /*
@@ -59,4 +63,92 @@ impl Visit for ObjectLitFinder {
   fn visit_object_lit(&mut self, n: &ObjectLit) {
       self.res = Some(n.clone());
   }
-}
\ No newline at end of file
+}
+
+/*
+  Counts the references left to a set of bindings, keeping where each one is so leftovers can be
+  reported. Import specifiers, declarations, property names and member properties aren't references.
+*/
+pub(crate) struct BindingRefs {
+  ids: HashSet<Id>,
+  pub refs: HashMap<Id, Vec<Span>>,
+}
+
+impl BindingRefs {
+  pub fn new(ids: HashSet<Id>) -> Self {
+      BindingRefs { ids, refs: HashMap::new() }
+  }
+
+  pub fn spans(&self, id: &Id) -> &[Span] {
+      self.refs.get(id).map_or(&[], |spans| spans.as_slice())
+  }
+}
+
+impl Visit for BindingRefs {
+  fn visit_ident(&mut self, n: &Ident) {
+      let id = n.to_id();
+      if self.ids.contains(&id) {
+          self.refs.entry(id).or_default().push(n.span);
+      }
+  }
+
+  fn visit_import_decl(&mut self, _: &ImportDecl) {}
+
+  fn visit_binding_ident(&mut self, n: &BindingIdent) {
+      n.type_ann.visit_with(self);
+  }
+
+  fn visit_prop_name(&mut self, n: &PropName) {
+      if let PropName::Computed(c) = n {
+          c.visit_with(self);
+      }
+  }
+
+  fn visit_member_prop(&mut self, n: &MemberProp) {
+      if let MemberProp::Computed(c) = n {
+          c.visit_with(self);
+      }
+  }
+
+  fn visit_jsx_attr_name(&mut self, _: &JSXAttrName) {}
+
+  // `</$Walnut>` is the same usage as its opening element.
+  fn visit_jsx_closing_element(&mut self, _: &JSXClosingElement) {}
+}
+
+/*
+  The name of a JSX element as written, e.g. `Walnut.Only` for <Walnut.Only>.
+*/
+pub(crate) fn jsx_element_name(name: &JSXElementName) -> String {
+  fn object_name(obj: &JSXObject) -> String {
+      match obj {
+          JSXObject::Ident(i) => String::from(&*i.sym),
+          JSXObject::JSXMemberExpr(m) => format!("{}.{}", object_name(&m.obj), m.prop.sym),
+      }
+  }
+
+  match name {
+      JSXElementName::Ident(i) => String::from(&*i.sym),
+      JSXElementName::JSXMemberExpr(m) => format!("{}.{}", object_name(&m.obj), m.prop.sym),
+      JSXElementName::JSXNamespacedName(n) => format!("{}:{}", n.ns.sym, n.name.sym),
+  }
+}
+
+/*
+  The package loaded by `require("walnut-ts")`, when it's one of `packages`.
+*/
+pub(crate) fn walnut_require_source(e: &Expr, packages: &[String]) -> Option<String> {
+  let Expr::Call(CallExpr { callee: Callee::Expr(callee), args, .. }) = e else {
+      return None;
+  };
+  if !matches!(&**callee, Expr::Ident(i) if &*i.sym == "require") {
+      return None;
+  }
+  match args.first().map(|a| &*a.expr) {
+      Some(Expr::Lit(Lit::Str(s))) => {
+          let src = s.value.to_string_lossy().into_owned();
+          is_walnut_package(&src, packages).then_some(src)
+      }
+      _ => None,
+  }
+}
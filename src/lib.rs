@@ -1,5 +1,7 @@
 #![deny(clippy::all)]
 
+use napi::Either;
+
 use crate::transform::WalnutHandler;
 
 mod transform;
@@ -8,18 +10,80 @@ mod helpers;
 mod finalize;
 mod resolver;
 mod resolve_modules;
+mod diagnostics;
+mod builtins;
+mod static_eval;
+mod condition;
+mod options;
+mod usage;
+#[cfg(test)]
+mod fixture;
 
-use crate::resolve_modules::resolve_deps;
+use crate::resolve_modules::{ resolve_deps, resolve_variants as resolve_variant_modules, DependencyGraph, DependencyOptions, VariantReport };
+use crate::resolver::invalidate_resolver_cache as invalidate_cache;
+use crate::scan_first::might_contain_walnut as mentions_walnut;
+use crate::options::WalnutOptions;
+use crate::usage::{ index_usages as index_project_usages, UsageIndex };
 
 #[macro_use]
 extern crate napi_derive;
 
 #[napi]
-pub fn get_handler(code: String, id: String, walnut_key: String) -> WalnutHandler {
-    WalnutHandler::new(code, id, walnut_key)
+pub fn get_handler(code: String, id: String, walnut_key: String, options: Option<WalnutOptions>) -> WalnutHandler {
+    WalnutHandler::new(code, id, walnut_key, options.unwrap_or_default())
+}
+
+// The text check a handler runs before parsing, so plugins can skip a file without creating one.
+#[napi]
+pub fn might_contain_walnut(code: String, options: Option<WalnutOptions>) -> bool {
+    mentions_walnut(&code, &options.unwrap_or_default())
+}
+
+#[napi]
+pub fn resolve_dependencies(
+    base: String,
+    entries: Either<String, Vec<String>>,
+    options: Option<WalnutOptions>,
+    graph_options: Option<DependencyOptions>
+) -> DependencyGraph {
+    resolve_deps(&base, &entry_list(entries), &options.unwrap_or_default(), &graph_options.unwrap_or_default())
+}
+
+#[napi]
+pub fn resolve_variants(
+    base: String,
+    entries: Either<String, Vec<String>>,
+    keys: Vec<String>,
+    options: Option<WalnutOptions>,
+    graph_options: Option<DependencyOptions>
+) -> VariantReport {
+    resolve_variant_modules(
+        &base,
+        &entry_list(entries),
+        &keys,
+        &options.unwrap_or_default(),
+        &graph_options.unwrap_or_default()
+    )
+}
+
+#[napi]
+pub fn index_usages(
+    base: String,
+    entries: Either<String, Vec<String>>,
+    options: Option<WalnutOptions>,
+    graph_options: Option<DependencyOptions>
+) -> UsageIndex {
+    index_project_usages(&base, &entry_list(entries), &options.unwrap_or_default(), &graph_options.unwrap_or_default())
+}
+
+fn entry_list(entries: Either<String, Vec<String>>) -> Vec<String> {
+    match entries {
+        Either::A(entry) => Vec::from([entry]),
+        Either::B(entries) => entries,
+    }
 }
 
 #[napi]
-pub fn resolve_dependencies(base: String, entry: String) {
-    resolve_deps(&base, &entry);
+pub fn invalidate_resolver_cache(path: Option<String>) {
+    invalidate_cache(path);
 }
\ No newline at end of file
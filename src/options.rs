@@ -0,0 +1,74 @@
+#![deny(clippy::all)]
+
+use std::{ collections::HashMap, path::PathBuf };
+
+use crate::resolver::ResolverConfig;
+
+pub(crate) const DEFAULT_PACKAGES: [&str; 1] = ["walnut-ts"];
+
+/*
+    Options passed in from JS, every field is optional.
+        jsxNames    extra component names treated as <$Walnut>, e.g. "Variant", "Walnut.Only" or "W.Case".
+                    `.Else` and `.Switch` work on them the same way they do on $Walnut.
+        packages    the packages walnut is imported from, "walnut-ts" by default. Subpaths
+                    ("walnut-ts/jsx") count as the package, other names containing it don't.
+        baseUrl     tsconfig style `baseUrl` and `paths` used when resolving imports, so aliased
+        paths       wrapper modules (`@/lib/variants`) and resolvers can be found.
+*/
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct WalnutOptions {
+    pub jsx_names: Option<Vec<String>>,
+    pub packages: Option<Vec<String>>,
+    pub base_url: Option<String>,
+    pub paths: Option<HashMap<String, Vec<String>>>,
+}
+
+impl WalnutOptions {
+    pub(crate) fn resolver_config(&self) -> ResolverConfig {
+        let mut paths: Vec<(String, Vec<String>)> = self.paths
+            .iter()
+            .flatten()
+            .map(|(from, to)| (from.clone(), to.clone()))
+            .collect();
+        paths.sort();
+
+        ResolverConfig {
+            base_url: self.base_url.as_ref().map(PathBuf::from),
+            paths,
+        }
+    }
+
+    pub(crate) fn packages(&self) -> Vec<String> {
+        let packages: Vec<String> = self.packages
+            .iter()
+            .flatten()
+            .map(|p| String::from(p.trim().trim_end_matches('/')))
+            .filter(|p| !p.is_empty())
+            .collect();
+
+        if packages.is_empty() {
+            return DEFAULT_PACKAGES.iter().map(|p| String::from(*p)).collect();
+        }
+        packages
+    }
+
+    pub(crate) fn jsx_names(&self) -> Vec<String> {
+        self.jsx_names
+            .iter()
+            .flatten()
+            .map(|n| String::from(n.trim()))
+            .filter(|n| !n.is_empty())
+            .collect()
+    }
+}
+
+// Whether `src` imports one of `packages` or a subpath of it.
+pub(crate) fn is_walnut_package(src: &str, packages: &[String]) -> bool {
+    packages.iter().any(|p| {
+        match src.strip_prefix(p.as_str()) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/'),
+            None => false,
+        }
+    })
+}
@@ -1,60 +1,742 @@
 #![deny(clippy::all)]
 
-use std::{ collections::HashMap, path::{ Path, PathBuf } };
-use swc::resolver;
-use swc_common::{
-    collections::AHashMap,
-    errors::{ ColorConfig, Handler },
-    sync::Lrc,
-    FileName,
-    SourceMap,
-};
+use std::{ collections::{ HashMap, HashSet, VecDeque }, fs, path::{ Component, Path, PathBuf }, sync::Arc };
+use swc_common::{ errors::{ ColorConfig, Handler }, sync::Lrc, FileName, SourceFile, SourceMap };
 use swc_ecma_ast::*;
-use swc_ecma_loader::{
-    resolve::Resolve,
-    TargetEnv,
-    resolvers::{ tsc::TsConfigResolver, lru::CachingResolver, node::NodeModulesResolver },
-};
-use swc_ecma_parser::{ Syntax, TsConfig };
+use swc_ecma_loader::resolve::Resolve;
+use swc_ecma_parser::{ Syntax, TsSyntax };
 use swc_ecma_visit::{ Visit, VisitWith };
 
-// fn get_file_resolver(
-//   base: &String
-// ) -> CachingResolver<TsConfigResolver<NodeModulesResolver>> {
-//   resolver::paths_resolver(
-//       TargetEnv::Node,
-//       AHashMap::default(),
-//       PathBuf::from(base.clone()),
-//       Vec::new(),
-//       true
-//   )
-// }
+use crate::helpers::BindingRefs;
+use crate::options::WalnutOptions;
+use crate::resolver::{ get_file_resolver, in_node_modules, static_string, FileResolver, ResolverConfig, WalnutReExports };
+use crate::scan_first::{ might_contain_walnut, ScanFirst };
+use crate::transform::WalnutHandler;
 
-fn get_file_resolver(base: &String) -> CachingResolver<TsConfigResolver<NodeModulesResolver>> {
-    let r = TsConfigResolver::new(
-        NodeModulesResolver::new(TargetEnv::Node, AHashMap::default(), true),
-        PathBuf::from(base.clone()),
-        Vec::new()
-    );
+/*
+    How far the dependency graph reaches.
+        includeNodeModules  walk into packages too. Off by default, packages still show up
+                            as nodes but their own imports aren't followed.
+        walnutKey           the graph as it is after transforming for this key, imports only
+                            the dropped variants used don't count
+*/
+#[napi(object)]
+#[derive(Clone, Debug, Default)]
+pub struct DependencyOptions {
+    pub include_node_modules: Option<bool>,
+    pub walnut_key: Option<String>,
+}
+
+/*
+    A module in the dependency graph.
+        kind    ts, tsx, dts, js, jsx, json, asset, or builtin for node builtins and other
+                modules that don't resolve to a file
+        error   why the module couldn't be read or parsed, its imports are missing then
+*/
+#[napi(object)]
+#[derive(Clone, Debug)]
+pub struct DependencyNode {
+    pub path: String,
+    pub kind: String,
+    pub contains_walnut: bool,
+    pub is_entry: bool,
+    pub error: Option<String>,
+}
+
+/*
+    An import from one module of another. `to` is missing when the specifier didn't resolve,
+    `error` says why.
+        kind        import, export (`export ... from`), export-all (`export * from`),
+                    dynamic-import, require (also `import x = require()`), url
+                    (`new URL(.., import.meta.url)`) or glob, one edge per file an
+                    `import.meta.glob` pattern matches
+        typeOnly    `import type`, `export type` and `import("..")` types, gone at runtime
+*/
+#[napi(object)]
+#[derive(Clone, Debug)]
+pub struct DependencyEdge {
+    pub from: String,
+    pub to: Option<String>,
+    pub specifier: String,
+    pub kind: String,
+    pub type_only: bool,
+    pub error: Option<String>,
+}
+
+#[napi]
+pub struct DependencyGraph {
+    nodes: Vec<DependencyNode>,
+    edges: Vec<DependencyEdge>,
+    // See get_cycles.
+    cycles: Vec<Vec<String>>,
+}
+
+#[napi]
+impl DependencyGraph {
+    #[napi]
+    pub fn get_nodes(&self) -> Vec<DependencyNode> {
+        self.nodes.clone()
+    }
+
+    #[napi]
+    pub fn get_edges(&self) -> Vec<DependencyEdge> {
+        self.edges.clone()
+    }
+
+    /// Import cycles as the paths along them, starting and ending with the same module.
+    /// Every module that is part of a cycle is on at least one of them, but not every distinct
+    /// cycle through it is listed.
+    #[napi]
+    pub fn get_cycles(&self) -> Vec<Vec<String>> {
+        self.cycles.clone()
+    }
+
+    #[napi]
+    pub fn to_json(&self) -> String {
+        let nodes: Vec<serde_json::Value> = self.nodes
+            .iter()
+            .map(|n| {
+                serde_json::json!({
+                    "path": n.path,
+                    "kind": n.kind,
+                    "containsWalnut": n.contains_walnut,
+                    "isEntry": n.is_entry,
+                    "error": n.error,
+                })
+            })
+            .collect();
+        let edges: Vec<serde_json::Value> = self.edges
+            .iter()
+            .map(|e| {
+                serde_json::json!({
+                    "from": e.from,
+                    "to": e.to,
+                    "specifier": e.specifier,
+                    "kind": e.kind,
+                    "typeOnly": e.type_only,
+                    "error": e.error,
+                })
+            })
+            .collect();
+
+        serde_json::to_string_pretty(
+            &serde_json::json!({ "nodes": nodes, "edges": edges, "cycles": self.cycles })
+        ).unwrap_or_default()
+    }
+
+    /*
+        Graphviz output. Modules using walnut are filled in, entries drawn bold and modules that
+        failed to parse outlined in red. Type-only imports are dotted, unresolved imports point
+        at a dashed red placeholder named after the specifier.
+    */
+    #[napi]
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph dependencies {\n  node [shape=box];\n");
+
+        for node in self.nodes.iter() {
+            let mut style = String::new();
+            if node.contains_walnut {
+                style.push_str(", style=filled, fillcolor=\"#d9ead3\"");
+            }
+            if node.is_entry {
+                style.push_str(", penwidth=2");
+            }
+            if node.error.is_some() {
+                style.push_str(", color=red");
+            }
+            out.push_str(&format!("  {} [label={}{style}];\n", dot_id(&node.path), dot_id(&node.path)));
+        }
+
+        for edge in self.edges.iter() {
+            match &edge.to {
+                Some(to) => {
+                    let style = if edge.type_only { ", style=dotted" } else { "" };
+                    out.push_str(
+                        &format!(
+                            "  {} -> {} [label={}{style}];\n",
+                            dot_id(&edge.from),
+                            dot_id(to),
+                            dot_id(&edge.specifier)
+                        )
+                    );
+                }
+                None => {
+                    let missing = dot_id(&format!("unresolved:{}", edge.specifier));
+                    out.push_str(
+                        &format!(
+                            "  {missing} [label={}, color=red, style=dashed];\n  {} -> {missing} [color=red, style=dashed];\n",
+                            dot_id(&edge.specifier),
+                            dot_id(&edge.from)
+                        )
+                    );
+                }
+            }
+        }
+
+        out.push_str("}\n");
+        out
+    }
+}
+
+impl DependencyGraph {
+    // Modules reachable from the entries at runtime, type-only imports aren't followed.
+    pub(crate) fn runtime_modules(&self) -> Vec<String> {
+        let node_index: HashMap<&str, usize> = self.nodes
+            .iter()
+            .enumerate()
+            .map(|(i, n)| (n.path.as_str(), i))
+            .collect();
+
+        let mut adjacency: Vec<Vec<usize>> = vec![Vec::new(); self.nodes.len()];
+        for edge in self.edges.iter().filter(|e| !e.type_only) {
+            let (Some(&from), Some(&to)) = (node_index.get(edge.from.as_str()), edge.to.as_ref().and_then(|t| node_index.get(t.as_str()))) else {
+                continue;
+            };
+            adjacency[from].push(to);
+        }
+
+        let mut seen = vec![false; self.nodes.len()];
+        let mut queue: VecDeque<usize> = VecDeque::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.is_entry && !seen[i] {
+                seen[i] = true;
+                queue.push_back(i);
+            }
+        }
+
+        while let Some(node) = queue.pop_front() {
+            for &to in adjacency[node].iter() {
+                if !seen[to] {
+                    seen[to] = true;
+                    queue.push_back(to);
+                }
+            }
+        }
+
+        // Graph order rather than the order they were reached in.
+        self.nodes
+            .iter()
+            .zip(seen)
+            .filter(|(_, seen)| *seen)
+            .map(|(n, _)| n.path.clone())
+            .collect()
+    }
+}
+
+/*
+    The modules one walnut key ships, i.e. reaches at runtime once transformed.
+*/
+#[napi(object)]
+#[derive(Clone, Debug)]
+pub struct VariantModules {
+    pub key: String,
+    pub modules: Vec<String>,
+}
+
+/*
+    Modules per key, and `dead` ones the untransformed graph reaches but no key does.
+*/
+#[napi(object)]
+#[derive(Clone, Debug)]
+pub struct VariantReport {
+    pub variants: Vec<VariantModules>,
+    pub dead: Vec<String>,
+}
 
-    let cr: CachingResolver<TsConfigResolver<NodeModulesResolver>> = CachingResolver::new(40, r);
+fn dot_id(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/*
+    Builds the dependency graph of everything reachable from `entries`.
+    `base` is the `baseUrl` bare specifiers are also looked up from.
+*/
+pub(crate) fn resolve_deps(
+    base: &String,
+    entries: &[String],
+    options: &WalnutOptions,
+    graph_options: &DependencyOptions
+) -> DependencyGraph {
+    let config = ResolverConfig {
+        base_url: Some(PathBuf::from(base)),
+        paths: options.resolver_config().paths,
+    };
+    let mut builder = GraphBuilder::new(options, graph_options, config);
+
+    for entry in entries.iter() {
+        // Imports resolve to absolute paths, entries have to match those.
+        let path = fs::canonicalize(entry).unwrap_or_else(|_| PathBuf::from(entry));
+        builder.add_entry(&FileName::Real(path));
+    }
+    builder.walk();
 
-    cr
+    builder.finish()
 }
 
-pub(crate) fn resolve_deps(base: &String, entry_id: &String) {
+/*
+    Walks the graph once untransformed and once per key, see VariantReport.
+*/
+pub(crate) fn resolve_variants(
+    base: &String,
+    entries: &[String],
+    keys: &[String],
+    options: &WalnutOptions,
+    graph_options: &DependencyOptions
+) -> VariantReport {
+    let untransformed = DependencyOptions { walnut_key: None, ..graph_options.clone() };
+    let all = resolve_deps(base, entries, options, &untransformed).runtime_modules();
+
+    let mut shipped: HashSet<String> = HashSet::new();
+    let variants: Vec<VariantModules> = keys
+        .iter()
+        .map(|key| {
+            let keyed = DependencyOptions { walnut_key: Some(key.clone()), ..graph_options.clone() };
+            let modules = resolve_deps(base, entries, options, &keyed).runtime_modules();
+            shipped.extend(modules.iter().cloned());
+            VariantModules { key: key.clone(), modules }
+        })
+        .collect();
+
+    VariantReport {
+        variants,
+        dead: all
+            .into_iter()
+            .filter(|path| !shipped.contains(path))
+            .collect(),
+    }
+}
+
+struct GraphBuilder<'a> {
+    options: &'a WalnutOptions,
+    include_node_modules: bool,
+    walnut_key: Option<String>,
+    config: ResolverConfig,
+    file_resolver: Arc<FileResolver>,
+    nodes: Vec<DependencyNode>,
+    node_index: HashMap<String, usize>,
+    edges: Vec<DependencyEdge>,
+    queue: VecDeque<FileName>,
+}
+
+impl<'a> GraphBuilder<'a> {
+    fn new(options: &'a WalnutOptions, graph_options: &DependencyOptions, config: ResolverConfig) -> Self {
+        GraphBuilder {
+            options,
+            include_node_modules: graph_options.include_node_modules.unwrap_or(false),
+            walnut_key: graph_options.walnut_key.clone(),
+            file_resolver: get_file_resolver(&config),
+            config,
+            nodes: Vec::new(),
+            node_index: HashMap::new(),
+            edges: Vec::new(),
+            queue: VecDeque::new(),
+        }
+    }
+
+    fn add_entry(&mut self, path: &FileName) {
+        let key = self.add_node(path);
+        self.nodes[self.node_index[&key]].is_entry = true;
+    }
+
+    // Adds a module once, queueing it up to be walked if its imports are followed.
+    fn add_node(&mut self, path: &FileName) -> String {
+        let key = path.to_string();
+        if self.node_index.contains_key(&key) {
+            return key;
+        }
+
+        let kind = module_kind(path);
+        if is_script_kind(kind) && (self.include_node_modules || !in_node_modules(path)) {
+            self.queue.push_back(path.clone());
+        }
+
+        self.node_index.insert(key.clone(), self.nodes.len());
+        self.nodes.push(DependencyNode {
+            path: key.clone(),
+            kind: String::from(kind),
+            contains_walnut: false,
+            is_entry: false,
+            error: None,
+        });
+        key
+    }
+
+    fn walk(&mut self) {
+        while let Some(path) = self.queue.pop_front() {
+            let idx = self.node_index[&path.to_string()];
+
+            let (code, program) = match parse_file(&path) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    self.nodes[idx].error = Some(e);
+                    continue;
+                }
+            };
+            let contains_walnut = self.scan_walnut(&path, &code, &program);
+            self.nodes[idx].contains_walnut = contains_walnut;
+
+            let program = match &self.walnut_key {
+                Some(key) if contains_walnut => self.transform(&path, code, key, program),
+                _ => program,
+            };
+
+            let mut import_finder = ImportFinder::new();
+            program.visit_with(&mut import_finder);
+
+            for found in import_finder.imports {
+                self.add_edge(&path, found);
+            }
+        }
+    }
+
+    fn add_edge(&mut self, from: &FileName, found: FoundImport) {
+        let resolved = match found.kind {
+            "glob" => return self.add_glob_edges(from, found),
+            "url" => resolve_url(from, &found.specifier),
+            _ => self.file_resolver.resolve(from, &found.specifier).map(|res| res.filename).map_err(|e| format!("{e:?}")),
+        };
+        let (to, error) = match resolved {
+            Ok(path) => (Some(self.add_node(&path)), None),
+            Err(e) => (None, Some(e)),
+        };
+
+        self.edges.push(DependencyEdge {
+            from: from.to_string(),
+            to,
+            specifier: found.specifier,
+            kind: String::from(found.kind),
+            type_only: found.type_only,
+            error,
+        });
+    }
+
+    // One edge per file the pattern matches, or a single unresolved one if none do.
+    fn add_glob_edges(&mut self, from: &FileName, found: FoundImport) {
+        let matched = match from {
+            FileName::Real(p) => {
+                let dir = p.parent().unwrap_or(Path::new("/"));
+                // Like vite, `/` patterns are relative to the project root.
+                let root = self.config.base_url.as_deref().unwrap_or(dir);
+                expand_glob(dir, root, &found.specifier, &found.ignore)
+            }
+            _ => Err(format!("{from} is not a file")),
+        };
+
+        let mut edge = DependencyEdge {
+            from: from.to_string(),
+            to: None,
+            specifier: found.specifier,
+            kind: String::from(found.kind),
+            type_only: found.type_only,
+            error: None,
+        };
+        match matched {
+            Ok(files) if files.is_empty() => {
+                edge.error = Some(String::from("no files match the pattern"));
+                self.edges.push(edge);
+            }
+            Ok(files) => {
+                for file in files {
+                    let to = self.add_node(&FileName::Real(file));
+                    self.edges.push(DependencyEdge { to: Some(to), ..edge.clone() });
+                }
+            }
+            Err(e) => {
+                edge.error = Some(e);
+                self.edges.push(edge);
+            }
+        }
+    }
+
+    // The same check WalnutHandler makes before transforming a file.
+    fn scan_walnut(&self, path: &FileName, code: &str, program: &Program) -> bool {
+        if !might_contain_walnut(code, self.options) {
+            return false;
+        }
+
+        let packages = self.options.packages();
+        let re_exports = WalnutReExports::new(&path.to_string(), &packages, &self.config);
+        let mut scan_first = ScanFirst::new(packages, self.options.jsx_names(), re_exports);
+        program.visit_with(&mut scan_first);
+
+        scan_first.should_run
+    }
+
+    /*
+        The program as WalnutHandler leaves it for `key`, minus the imports only the dropped
+        variants referred to. Compiling elides those, so they don't ship either.
+    */
+    fn transform(&self, path: &FileName, code: String, key: &str, original: Program) -> Program {
+        let handler = WalnutHandler::new(code, path.to_string(), String::from(key), self.options.clone());
+        let mut program = handler.transform_for_graph(original.clone());
+
+        let used_before = referenced_imports(&original);
+        let used_after = referenced_imports(&program);
+        if let Program::Module(module) = &mut program {
+            module.body.retain(|item| {
+                let ModuleItem::ModuleDecl(ModuleDecl::Import(import)) = item else {
+                    return true;
+                };
+                let src = import.src.value.to_string_lossy().into_owned();
+                // Side effect imports stay, as do imports that were never used to begin with.
+                import.specifiers.is_empty() ||
+                    import.specifiers.iter().any(|s| {
+                        let binding = (src.clone(), String::from(&*import_local(s).sym));
+                        !used_before.contains(&binding) || used_after.contains(&binding)
+                    })
+            });
+        }
+        program
+    }
+
+    fn finish(self) -> DependencyGraph {
+        let cycles = find_cycles(&self.nodes, &self.edges, &self.node_index);
+
+        DependencyGraph {
+            nodes: self.nodes,
+            edges: self.edges,
+            cycles,
+        }
+    }
+}
+
+/*
+    Finds import cycles. Modules are first grouped into strongly connected components, then each
+    group is covered with shortest cycles until every module in it is on one, so no module that
+    is part of a cycle goes unlisted. Type-only imports are left out, they can't cycle at runtime.
+*/
+fn find_cycles(
+    nodes: &[DependencyNode],
+    edges: &[DependencyEdge],
+    node_index: &HashMap<String, usize>
+) -> Vec<Vec<String>> {
+    let mut adjacency: Vec<Vec<usize>> = vec![Vec::new(); nodes.len()];
+    for edge in edges.iter().filter(|e| !e.type_only) {
+        let (Some(&from), Some(to)) = (node_index.get(&edge.from), edge.to.as_ref().and_then(|t| node_index.get(t))) else {
+            continue;
+        };
+        if !adjacency[from].contains(to) {
+            adjacency[from].push(*to);
+        }
+    }
+
+    let mut cycles: Vec<Vec<String>> = Vec::new();
+    let mut in_group = vec![false; nodes.len()];
+    for mut group in strongly_connected(&adjacency) {
+        // A lone module only cycles through an import of itself.
+        if group.len() == 1 && !adjacency[group[0]].contains(&group[0]) {
+            continue;
+        }
+
+        group.sort_unstable();
+        for &m in group.iter() {
+            in_group[m] = true;
+        }
+
+        let mut covered: HashSet<usize> = HashSet::new();
+        for &m in group.iter() {
+            if covered.contains(&m) {
+                continue;
+            }
+            let cycle = shortest_cycle(m, &adjacency, &in_group);
+            covered.extend(cycle.iter().copied());
+
+            let mut paths: Vec<String> = cycle.iter().map(|n| nodes[*n].path.clone()).collect();
+            paths.push(nodes[m].path.clone());
+            cycles.push(paths);
+        }
+
+        for &m in group.iter() {
+            in_group[m] = false;
+        }
+    }
+
+    cycles
+}
+
+/*
+    Tarjan's strongly connected components, walked with an explicit stack so deep graphs can't
+    overflow.
+*/
+fn strongly_connected(adjacency: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    const UNSEEN: usize = usize::MAX;
+
+    let mut index = vec![UNSEEN; adjacency.len()];
+    let mut low = vec![0; adjacency.len()];
+    let mut on_stack = vec![false; adjacency.len()];
+    let mut stack: Vec<usize> = Vec::new();
+    let mut next_index = 0;
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+
+    for start in 0..adjacency.len() {
+        if index[start] != UNSEEN {
+            continue;
+        }
+
+        index[start] = next_index;
+        low[start] = next_index;
+        next_index += 1;
+        stack.push(start);
+        on_stack[start] = true;
+
+        // (module, index of the next edge to follow)
+        let mut walk: Vec<(usize, usize)> = vec![(start, 0)];
+        while let Some((node, next)) = walk.last_mut() {
+            let node = *node;
+            if let Some(&target) = adjacency[node].get(*next) {
+                *next += 1;
+                if index[target] == UNSEEN {
+                    index[target] = next_index;
+                    low[target] = next_index;
+                    next_index += 1;
+                    stack.push(target);
+                    on_stack[target] = true;
+                    walk.push((target, 0));
+                } else if on_stack[target] {
+                    low[node] = low[node].min(index[target]);
+                }
+                continue;
+            }
+
+            walk.pop();
+            if let Some((parent, _)) = walk.last() {
+                low[*parent] = low[*parent].min(low[node]);
+            }
+            if low[node] == index[node] {
+                let mut group = Vec::new();
+                while let Some(m) = stack.pop() {
+                    on_stack[m] = false;
+                    group.push(m);
+                    if m == node {
+                        break;
+                    }
+                }
+                groups.push(group);
+            }
+        }
+    }
+
+    groups
+}
+
+// The shortest cycle from `start` back to itself that stays within its group, in walk order.
+fn shortest_cycle(start: usize, adjacency: &[Vec<usize>], in_group: &[bool]) -> Vec<usize> {
+    let mut prev: HashMap<usize, usize> = HashMap::new();
+    let mut queue: VecDeque<usize> = VecDeque::from([start]);
+
+    while let Some(node) = queue.pop_front() {
+        for &target in adjacency[node].iter() {
+            if target == start {
+                let mut cycle = vec![node];
+                let mut at = node;
+                while let Some(&p) = prev.get(&at) {
+                    cycle.push(p);
+                    at = p;
+                }
+                cycle.reverse();
+                return cycle;
+            }
+            if in_group[target] && !prev.contains_key(&target) {
+                prev.insert(target, node);
+                queue.push_back(target);
+            }
+        }
+    }
+
+    // Every module in a group with more than one module is on a cycle.
+    vec![start]
+}
+
+/*
+    Imported bindings something still refers to, as (source, local name) so the same import
+    lines up before and after transforming.
+*/
+fn referenced_imports(program: &Program) -> HashSet<(String, String)> {
+    let mut imports: HashMap<Id, String> = HashMap::new();
+    if let Program::Module(module) = program {
+        for item in module.body.iter() {
+            if let ModuleItem::ModuleDecl(ModuleDecl::Import(import)) = item {
+                for s in import.specifiers.iter() {
+                    imports.insert(import_local(s).to_id(), import.src.value.to_string_lossy().into_owned());
+                }
+            }
+        }
+    }
+
+    let mut refs = BindingRefs::new(imports.keys().cloned().collect());
+    program.visit_with(&mut refs);
+
+    imports
+        .into_iter()
+        .filter(|(id, _)| !refs.spans(id).is_empty())
+        .map(|(id, src)| (src, String::from(&*id.0)))
+        .collect()
+}
+
+fn import_local(s: &ImportSpecifier) -> &Ident {
+    match s {
+        ImportSpecifier::Named(n) => &n.local,
+        ImportSpecifier::Default(d) => &d.local,
+        ImportSpecifier::Namespace(n) => &n.local,
+    }
+}
+
+fn module_kind(path: &FileName) -> &'static str {
+    let FileName::Real(p) = path else {
+        return "builtin";
+    };
+
+    let name = p.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    if name.ends_with(".d.ts") || name.ends_with(".d.mts") || name.ends_with(".d.cts") {
+        return "dts";
+    }
+
+    match Path::new(&name).extension().and_then(|e| e.to_str()) {
+        Some("ts" | "mts" | "cts") => "ts",
+        Some("tsx") => "tsx",
+        Some("js" | "mjs" | "cjs") => "js",
+        Some("jsx") => "jsx",
+        Some("json") => "json",
+        _ => "asset",
+    }
+}
+
+fn is_script_kind(kind: &str) -> bool {
+    matches!(kind, "ts" | "tsx" | "js" | "jsx")
+}
+
+// The module's source along with its program, the file is only read once.
+fn parse_file(path: &FileName) -> Result<(String, Program), String> {
     let cm = Lrc::<SourceMap>::default();
+    let fm = load_file_in(&cm, path)?;
+    let code = fm.src.to_string();
+    Ok((code, parse_source(&cm, fm)?))
+}
+
+// Parses into a given source map, for callers that turn spans into positions afterwards.
+pub(crate) fn parse_file_in(cm: &Lrc<SourceMap>, path: &FileName) -> Result<Program, String> {
+    let fm = load_file_in(cm, path)?;
+    parse_source(cm, fm)
+}
+
+fn load_file_in(cm: &Lrc<SourceMap>, path: &FileName) -> Result<Lrc<SourceFile>, String> {
+    let FileName::Real(p) = path else {
+        return Err(format!("{path} is not a file"));
+    };
+    cm.load_file(p).map_err(|e| format!("failed to read {}: {e}", p.display()))
+}
+
+fn parse_source(cm: &Lrc<SourceMap>, fm: Lrc<SourceFile>) -> Result<Program, String> {
     let handler = Handler::with_tty_emitter(ColorConfig::Auto, true, false, Some(cm.clone()));
     let compiler = swc::Compiler::new(cm.clone());
+    let name = fm.name.to_string();
 
-    let fm = cm.load_file(Path::new(&entry_id.to_string())).expect("Something gone wrong.");
-
-    let program = compiler
+    compiler
         .parse_js(
             fm,
             &handler,
             EsVersion::Es2020,
-            Syntax::Typescript(TsConfig {
+            Syntax::Typescript(TsSyntax {
                 tsx: true,
                 decorators: false,
                 dts: false,
@@ -64,33 +746,582 @@ pub(crate) fn resolve_deps(base: &String, entry_id: &String) {
             swc::config::IsModule::Bool(true),
             Default::default()
         )
-        .expect("Failed to parse");
+        .map_err(|e| format!("failed to parse {name}: {e}"))
+}
 
-    let mut import_finder = ImportFinder::new(base, entry_id);
+// `new URL("./x", import.meta.url)` points at a file relative to the module, no lookup.
+fn resolve_url(from: &FileName, specifier: &str) -> Result<FileName, String> {
+    let FileName::Real(p) = from else {
+        return Err(format!("{from} is not a file"));
+    };
+    let path = normalize_path(&p.parent().unwrap_or(Path::new("/")).join(specifier));
+    if path.is_file() {
+        Ok(FileName::Real(path))
+    } else {
+        Err(format!("{} does not exist", path.display()))
+    }
+}
 
-    program.visit_with(&mut import_finder);
+// Resolves `.` and `..` without touching the filesystem, globs can't be canonicalized.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/*
+    Files an `import.meta.glob` pattern matches, expanded the way vite does: relative to the
+    importing module, or to `root` for patterns starting with `/`. Supports `*`, `?`, `**` and
+    `{a,b}`, `ignore` holds the `!` patterns next to it. node_modules is never matched.
+*/
+fn expand_glob(dir: &Path, root: &Path, pattern: &str, ignore: &[String]) -> Result<Vec<PathBuf>, String> {
+    let absolute = |p: &str| -> Result<PathBuf, String> {
+        if let Some(rest) = p.strip_prefix('/') {
+            Ok(normalize_path(&root.join(rest)))
+        } else if p.starts_with("./") || p.starts_with("../") {
+            Ok(normalize_path(&dir.join(p)))
+        } else {
+            Err(format!("glob pattern {p} has to start with ./, ../ or /"))
+        }
+    };
+
+    let mut ignored: Vec<Vec<String>> = Vec::new();
+    for p in ignore.iter().flat_map(|p| expand_braces(p)) {
+        ignored.push(glob_segments(&absolute(&p)?).1);
+    }
+
+    let mut files = Vec::new();
+    for p in expand_braces(pattern) {
+        let (start, segments) = glob_segments(&absolute(&p)?);
+        // Walk from the deepest directory the pattern names outright.
+        let fixed = segments.iter().take_while(|s| !is_glob(s)).count();
+        let start: PathBuf = segments[..fixed].iter().fold(start, |dir, s| dir.join(s));
+        glob_walk(&start, &segments[fixed..], &mut files);
+    }
+
+    files.retain(|f| {
+        let names = glob_segments(f).1;
+        !ignored.iter().any(|segments| glob_match(segments, &names))
+    });
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+// Splits an absolute path into its root and the names below it.
+fn glob_segments(path: &Path) -> (PathBuf, Vec<String>) {
+    let mut root = PathBuf::new();
+    let mut names = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => names.push(name.to_string_lossy().to_string()),
+            c => root.push(c),
+        }
+    }
+    (root, names)
+}
+
+fn is_glob(segment: &str) -> bool {
+    segment.contains(['*', '?'])
 }
 
+fn glob_walk(dir: &Path, segments: &[String], files: &mut Vec<PathBuf>) {
+    let Some((segment, rest)) = segments.split_first() else {
+        if dir.is_file() {
+            files.push(dir.to_path_buf());
+        }
+        return;
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    if segment == "**" {
+        // Matches no directory at all as well.
+        glob_walk(dir, rest, files);
+    }
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = entry.path();
+        if name == "node_modules" {
+            continue;
+        }
+        if segment == "**" {
+            if path.is_dir() && !name.starts_with('.') {
+                glob_walk(&path, segments, files);
+            }
+        } else if segment_match(segment, &name) {
+            glob_walk(&path, rest, files);
+        }
+    }
+}
+
+fn glob_match(segments: &[String], names: &[String]) -> bool {
+    match segments.split_first() {
+        None => names.is_empty(),
+        Some((segment, rest)) if segment == "**" => (0..=names.len()).any(|i| glob_match(rest, &names[i..])),
+        Some((segment, rest)) =>
+            names.split_first().is_some_and(|(name, names)| segment_match(segment, name) && glob_match(rest, names)),
+    }
+}
+
+// Wildcards don't match dotfiles unless the pattern asks for the dot.
+fn segment_match(segment: &str, name: &str) -> bool {
+    if name.starts_with('.') && !segment.starts_with('.') {
+        return false;
+    }
+    wildcard_match(&segment.chars().collect::<Vec<_>>(), &name.chars().collect::<Vec<_>>())
+}
+
+fn wildcard_match(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|i| wildcard_match(rest, &name[i..])),
+        Some(('?', rest)) => !name.is_empty() && wildcard_match(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && wildcard_match(rest, &name[1..]),
+    }
+}
+
+// `./{a,b}/*.ts` -> `./a/*.ts`, `./b/*.ts`, nested braces included.
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let Some(open) = pattern.find('{') else {
+        return vec![String::from(pattern)];
+    };
+
+    let mut depth = 0;
+    let mut part_start = open + 1;
+    let mut parts = Vec::new();
+    let mut close = None;
+    for (i, c) in pattern.char_indices().skip_while(|(i, _)| *i < open) {
+        match c {
+            '{' => {
+                depth += 1;
+            }
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    parts.push(&pattern[part_start..i]);
+                    close = Some(i);
+                    break;
+                }
+            }
+            ',' if depth == 1 => {
+                parts.push(&pattern[part_start..i]);
+                part_start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    let Some(close) = close else {
+        return vec![String::from(pattern)];
+    };
+    parts
+        .iter()
+        .flat_map(|part| expand_braces(&format!("{}{part}{}", &pattern[..open], &pattern[close + 1..])))
+        .collect()
+}
+
+struct FoundImport {
+    specifier: String,
+    kind: &'static str,
+    type_only: bool,
+    // `!` patterns given alongside a glob.
+    ignore: Vec<String>,
+}
+
+impl FoundImport {
+    fn new(specifier: String, kind: &'static str, type_only: bool) -> Self {
+        FoundImport { specifier, kind, type_only, ignore: Vec::new() }
+    }
+}
+
+/*
+    Collects the modules a program imports, statically and at runtime. Dynamic imports and
+    requires only count with a literal specifier.
+*/
 struct ImportFinder {
-    file_resolver: CachingResolver<TsConfigResolver<NodeModulesResolver>>,
-    entry: String,
-    base: String,
+    imports: Vec<FoundImport>,
 }
 
 impl ImportFinder {
-    pub fn new(base: &String, entry: &String) -> Self {
-        ImportFinder {
-            file_resolver: get_file_resolver(base),
-            entry: entry.clone(),
-            base: base.clone(),
+    pub fn new() -> Self {
+        ImportFinder { imports: Vec::new() }
+    }
+
+    // `import.meta.glob("./dir/*.ts")`, or a list of patterns with `!` exclusions.
+    fn find_glob(&mut self, args: &[ExprOrSpread]) {
+        let patterns: Vec<String> = match args.first().map(|a| &*a.expr) {
+            Some(Expr::Array(arr)) => arr.elems
+                .iter()
+                .flatten()
+                .filter_map(|e| static_string(&e.expr))
+                .collect(),
+            Some(e) => static_string(e).into_iter().collect(),
+            None => Vec::new(),
+        };
+
+        let (ignore, include): (Vec<String>, Vec<String>) = patterns.into_iter().partition(|p| p.starts_with('!'));
+        let ignore: Vec<String> = ignore.iter().map(|p| String::from(&p[1..])).collect();
+        for pattern in include {
+            self.imports.push(FoundImport { ignore: ignore.clone(), ..FoundImport::new(pattern, "glob", false) });
         }
     }
 }
 
+fn is_import_meta(e: &Expr) -> bool {
+    matches!(e, Expr::MetaProp(MetaPropExpr { kind: MetaPropKind::ImportMeta, .. }))
+}
+
+// `import.meta.<prop>`
+fn is_import_meta_prop(e: &Expr, prop: &str) -> bool {
+    match e {
+        Expr::Member(MemberExpr { obj, prop: MemberProp::Ident(p), .. }) => is_import_meta(obj) && &*p.sym == prop,
+        _ => false,
+    }
+}
+
 impl Visit for ImportFinder {
     fn visit_import_decl(&mut self, n: &ImportDecl) {
-        let base = FileName::Real(PathBuf::from(&self.entry));
-        let resolved = self.file_resolver.resolve(&base, &*n.src.value);
-        println!("{:?}", resolved);
+        // `import { type A, type B }` is as type-only as `import type { A, B }`.
+        let type_only =
+            n.type_only ||
+            (!n.specifiers.is_empty() &&
+                n.specifiers.iter().all(|s| matches!(s, ImportSpecifier::Named(named) if named.is_type_only)));
+        self.imports.push(FoundImport::new(n.src.value.to_string_lossy().into_owned(), "import", type_only));
+    }
+
+    fn visit_named_export(&mut self, n: &NamedExport) {
+        let Some(src) = &n.src else {
+            return;
+        };
+        let type_only =
+            n.type_only ||
+            (!n.specifiers.is_empty() &&
+                n.specifiers.iter().all(|s| matches!(s, ExportSpecifier::Named(named) if named.is_type_only)));
+        self.imports.push(FoundImport::new(src.value.to_string_lossy().into_owned(), "export", type_only));
+    }
+
+    fn visit_export_all(&mut self, n: &ExportAll) {
+        self.imports.push(FoundImport::new(n.src.value.to_string_lossy().into_owned(), "export-all", n.type_only));
+    }
+
+    fn visit_ts_import_equals_decl(&mut self, n: &TsImportEqualsDecl) {
+        if let TsModuleRef::TsExternalModuleRef(r) = &n.module_ref {
+            self.imports.push(FoundImport::new(r.expr.value.to_string_lossy().into_owned(), "require", n.is_type_only));
+        }
+    }
+
+    // `typeof import("./x")` and friends in type positions.
+    fn visit_ts_import_type(&mut self, n: &TsImportType) {
+        self.imports.push(FoundImport::new(n.arg.value.to_string_lossy().into_owned(), "import", true));
+        n.visit_children_with(self);
+    }
+
+    fn visit_call_expr(&mut self, n: &CallExpr) {
+        let arg = n.args.first().and_then(|a| static_string(&a.expr));
+        match &n.callee {
+            Callee::Import(_) => {
+                if let Some(specifier) = arg {
+                    self.imports.push(FoundImport::new(specifier, "dynamic-import", false));
+                }
+            }
+            Callee::Expr(callee) => {
+                match &**callee {
+                    Expr::Ident(i) if &*i.sym == "require" => {
+                        if let Some(specifier) = arg {
+                            self.imports.push(FoundImport::new(specifier, "require", false));
+                        }
+                    }
+                    e if is_import_meta_prop(e, "glob") => self.find_glob(&n.args),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        n.visit_children_with(self);
+    }
+
+    fn visit_new_expr(&mut self, n: &NewExpr) {
+        if let (Expr::Ident(i), Some(args)) = (&*n.callee, &n.args) {
+            let is_module_url =
+                &*i.sym == "URL" && args.len() >= 2 && is_import_meta_prop(&args[1].expr, "url");
+            if is_module_url {
+                // Anything with a scheme is outside the module graph.
+                if let Some(specifier) = static_string(&args[0].expr).filter(|s| !s.contains("://")) {
+                    self.imports.push(FoundImport::new(specifier, "url", false));
+                }
+            }
+        }
+        n.visit_children_with(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| String::from(*s)).collect()
+    }
+
+    fn segments(pattern: &str) -> Vec<String> {
+        pattern.split('/').map(String::from).collect()
+    }
+
+    fn tree(name: &str) -> Fixture {
+        let files: Vec<(&str, &str)> = FILES.iter().map(|f| (*f, "")).collect();
+        Fixture::new(name, &files)
+    }
+
+    fn paths(fixture: &Fixture, files: &[&str]) -> Vec<PathBuf> {
+        files.iter().map(|f| PathBuf::from(fixture.path(f))).collect()
+    }
+
+    const FILES: [&str; 7] = [
+        "src/a.ts",
+        "src/b.tsx",
+        "src/.hidden.ts",
+        "src/nested/c.ts",
+        "src/nested/deeper/d.ts",
+        "src/node_modules/pkg/e.ts",
+        "other/f.ts",
+    ];
+
+    #[test]
+    fn expands_braces() {
+        assert_eq!(expand_braces("./a/*.ts"), strings(&["./a/*.ts"]));
+        assert_eq!(expand_braces("./*.{ts,tsx}"), strings(&["./*.ts", "./*.tsx"]));
+        assert_eq!(expand_braces("./{a,b}/*.{js,ts}"), strings(&["./a/*.js", "./a/*.ts", "./b/*.js", "./b/*.ts"]));
+        assert_eq!(expand_braces("./{a,b{1,2}}.ts"), strings(&["./a.ts", "./b1.ts", "./b2.ts"]));
+        // An unclosed brace is taken literally.
+        assert_eq!(expand_braces("./{a,b.ts"), strings(&["./{a,b.ts"]));
+    }
+
+    #[test]
+    fn matches_wildcards_within_a_segment() {
+        assert!(segment_match("*.ts", "a.ts"));
+        assert!(!segment_match("*.ts", "a.tsx"));
+        assert!(segment_match("?.ts", "a.ts"));
+        assert!(!segment_match("?.ts", "ab.ts"));
+        assert!(segment_match("a*b*c", "aXXbYc"));
+        assert!(!segment_match("*.ts", ".hidden.ts"));
+        assert!(segment_match(".*.ts", ".hidden.ts"));
+    }
+
+    #[test]
+    fn matches_globstar_across_segments() {
+        let names = segments("src/nested/deeper/d.ts");
+        assert!(glob_match(&segments("src/**/*.ts"), &names));
+        assert!(glob_match(&segments("**/d.ts"), &names));
+        assert!(glob_match(&segments("src/**/deeper/**/d.ts"), &names));
+        assert!(!glob_match(&segments("src/*/d.ts"), &names));
+        assert!(glob_match(&segments("src/**/a.ts"), &segments("src/a.ts")));
+    }
+
+    #[test]
+    fn expands_relative_patterns() {
+        let tree = tree("glob-relative");
+        assert_eq!(expand_glob(tree.root(), tree.root(), "./src/*.ts", &[]), Ok(paths(&tree, &["src/a.ts"])));
+        assert_eq!(
+            expand_glob(tree.root(), tree.root(), "./src/*.{ts,tsx}", &[]),
+            Ok(paths(&tree, &["src/a.ts", "src/b.tsx"]))
+        );
+
+        let nested = tree.root().join("src/nested");
+        assert_eq!(expand_glob(&nested, tree.root(), "../*.ts", &[]), Ok(paths(&tree, &["src/a.ts"])));
+    }
+
+    #[test]
+    fn globstar_skips_node_modules_and_dotfiles() {
+        let tree = tree("glob-globstar");
+        assert_eq!(
+            expand_glob(tree.root(), tree.root(), "./src/**/*.ts", &[]),
+            Ok(paths(&tree, &["src/a.ts", "src/nested/c.ts", "src/nested/deeper/d.ts"]))
+        );
+    }
+
+    #[test]
+    fn ignore_patterns_exclude_matches() {
+        let tree = tree("glob-ignore");
+        assert_eq!(
+            expand_glob(tree.root(), tree.root(), "./src/**/*.ts", &strings(&["./src/nested/**"])),
+            Ok(paths(&tree, &["src/a.ts"]))
+        );
+    }
+
+    #[test]
+    fn root_patterns_start_at_the_root() {
+        let tree = tree("glob-root");
+        let src = tree.root().join("src");
+        assert_eq!(expand_glob(&src, tree.root(), "/other/*.ts", &[]), Ok(paths(&tree, &["other/f.ts"])));
+    }
+
+    #[test]
+    fn rejects_bare_patterns() {
+        let tree = tree("glob-bare");
+        assert!(expand_glob(tree.root(), tree.root(), "src/*.ts", &[]).is_err());
+        assert_eq!(expand_glob(tree.root(), tree.root(), "./missing/*.ts", &[]), Ok(Vec::new()));
+    }
+
+    fn graph(fixture: &Fixture, entry: &str) -> DependencyGraph {
+        let base = fixture.root().to_string_lossy().into_owned();
+        resolve_deps(&base, &[fixture.path(entry)], &WalnutOptions::default(), &DependencyOptions::default())
+    }
+
+    // Cycles with the fixture root taken off every path.
+    fn cycle_names(fixture: &Fixture, graph: &DependencyGraph) -> Vec<Vec<String>> {
+        let root = format!("{}/", fixture.root().display());
+        graph
+            .get_cycles()
+            .iter()
+            .map(|cycle| cycle.iter().map(|p| p.replacen(&root, "", 1)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn lists_every_module_on_a_cycle() {
+        // c only cycles through b and a, which already close a cycle of their own.
+        let fixture = Fixture::new(
+            "graph-cycles",
+            &[
+                ("a.ts", "import { b } from \"./b\";\nimport { c } from \"./c\";\nexport const a = b + c;\n"),
+                ("b.ts", "import { a } from \"./a\";\nexport const b = () => a;\n"),
+                ("c.ts", "import { b } from \"./b\";\nexport const c = b;\n"),
+            ]
+        );
+        let graph = graph(&fixture, "a.ts");
+
+        assert_eq!(
+            cycle_names(&fixture, &graph),
+            [strings(&["a.ts", "b.ts", "a.ts"]), strings(&["c.ts", "b.ts", "a.ts", "c.ts"])]
+        );
+    }
+
+    #[test]
+    fn type_only_imports_do_not_cycle() {
+        let fixture = Fixture::new(
+            "graph-type-cycles",
+            &[
+                ("a.ts", "import { b } from \"./b\";\nexport type A = string;\nexport const a = b;\n"),
+                ("b.ts", "import type { A } from \"./a\";\nexport const b: A = \"b\";\n"),
+                ("self.ts", "import { own } from \"./self\";\nexport const own = 1;\nexport const again = own;\n"),
+            ]
+        );
+
+        assert!(graph(&fixture, "a.ts").get_cycles().is_empty());
+        assert_eq!(cycle_names(&fixture, &graph(&fixture, "self.ts")), [strings(&["self.ts", "self.ts"])]);
+    }
+
+    #[test]
+    fn variants_only_keep_the_modules_their_key_uses() {
+        // The resolver can't be found, the per-key walks don't look it up.
+        let fixture = Fixture::new(
+            "graph-variants",
+            &[
+                (
+                    "app.ts",
+                    r#"import { $Resolve, $Val } from "walnut-ts";
+import { missing } from "./missing-resolver";
+import { ios } from "./ios";
+import { web } from "./web";
+
+export const platform = $Val({ ios: ios, web: web });
+export const theme = $Resolve(missing);
+"#,
+                ),
+                ("ios.ts", "export const ios = \"ios\";\n"),
+                ("web.ts", "export const web = \"web\";\n"),
+            ]
+        );
+        let base = fixture.root().to_string_lossy().into_owned();
+        let report = resolve_variants(
+            &base,
+            &[fixture.path("app.ts")],
+            &strings(&["ios"]),
+            &WalnutOptions::default(),
+            &DependencyOptions::default()
+        );
+
+        let mut modules = report.variants[0].modules.clone();
+        modules.sort();
+        assert_eq!(modules, [fixture.path("app.ts"), fixture.path("ios.ts")]);
+        assert_eq!(report.dead, [fixture.path("web.ts")]);
+    }
+
+    #[test]
+    fn every_import_form_becomes_an_edge() {
+        let fixture = Fixture::new(
+            "graph-edge-kinds",
+            &[
+                (
+                    "main.ts",
+                    r#"import { a } from "./a";
+import type { T } from "./types";
+export { b } from "./b";
+export * from "./c";
+const d = require("./d");
+import e = require("./e");
+const lazy = () => import("./lazy");
+const asset = new URL("./asset.svg", import.meta.url);
+const pages = import.meta.glob("./pages/*.ts");
+import { gone } from "./missing";
+export const all: T = [a, d, e, lazy, asset, pages, gone];
+"#,
+                ),
+                ("a.ts", "export const a = 1;\n"),
+                ("types.ts", "export type T = unknown[];\n"),
+                ("b.ts", "export const b = 1;\n"),
+                ("c.ts", "export const c = 1;\n"),
+                ("d.ts", "module.exports = 1;\n"),
+                ("e.ts", "export = 1;\n"),
+                ("lazy.ts", "export default 1;\n"),
+                ("asset.svg", "<svg />\n"),
+                ("pages/home.ts", "export default 1;\n"),
+                ("pages/about.ts", "export default 1;\n"),
+            ]
+        );
+        let graph = graph(&fixture, "main.ts");
+        let root = format!("{}/", fixture.root().display());
+
+        let mut edges: Vec<(String, String, bool)> = graph
+            .get_edges()
+            .iter()
+            .map(|e| {
+                let to = e.to.as_ref().map_or(String::from("-"), |t| t.replacen(&root, "", 1));
+                (e.kind.clone(), to, e.type_only)
+            })
+            .collect();
+        edges.sort();
+
+        let expected: Vec<(String, String, bool)> = [
+            ("dynamic-import", "lazy.ts", false),
+            ("export", "b.ts", false),
+            ("export-all", "c.ts", false),
+            ("glob", "pages/about.ts", false),
+            ("glob", "pages/home.ts", false),
+            ("import", "-", false),
+            ("import", "a.ts", false),
+            ("import", "types.ts", true),
+            ("require", "d.ts", false),
+            ("require", "e.ts", false),
+            ("url", "asset.svg", false),
+        ]
+            .iter()
+            .map(|(kind, to, type_only)| (String::from(*kind), String::from(*to), *type_only))
+            .collect();
+        assert_eq!(edges, expected);
+
+        let missing = graph.get_edges().into_iter().find(|e| e.specifier == "./missing").unwrap();
+        assert!(missing.error.is_some());
     }
 }
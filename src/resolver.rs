@@ -1,9 +1,14 @@
 #![deny(clippy::all)]
 
-use std::{ collections::HashMap, path::{ Path, PathBuf } };
-use swc::resolver;
+use std::{
+    collections::{ hash_map::DefaultHasher, HashMap, HashSet },
+    fs,
+    hash::{ Hash, Hasher },
+    path::{ Component, Path, PathBuf },
+    sync::{ Arc, Mutex, OnceLock },
+    time::SystemTime,
+};
 use swc_common::{
-    collections::AHashMap,
     errors::{ ColorConfig, Handler },
     sync::Lrc,
     FileName,
@@ -13,77 +18,447 @@ use swc_ecma_ast::*;
 use swc_ecma_loader::{
     resolve::Resolve,
     TargetEnv,
-    resolvers::{ tsc::TsConfigResolver, lru::CachingResolver, node::NodeModulesResolver },
+    resolvers::{ lru::CachingResolver, node::NodeModulesResolver, tsc::TsConfigResolver },
 };
-use swc_ecma_parser::{ Syntax, TsConfig };
+use swc_ecma_parser::{ Syntax, TsSyntax };
 use swc_ecma_visit::{ Visit, VisitWith };
 
-pub(crate) fn get_file_resolver(
-    cur_file: &String
-) -> CachingResolver<TsConfigResolver<NodeModulesResolver>> {
-    resolver::paths_resolver(
-        TargetEnv::Node,
-        AHashMap::default(),
-        PathBuf::from(cur_file.clone()),
-        Vec::new(),
-        true
-    )
+use crate::static_eval::{ ModuleStatics, StaticScope, StaticValue };
+use crate::helpers::walnut_require_source;
+use crate::options::is_walnut_package;
+use crate::transform::Intrinsic;
+
+pub(crate) type FileResolver = CachingResolver<Box<dyn Resolve>>;
+
+/*
+    How imports are resolved, node_modules resolution with an optional tsconfig style
+    `baseUrl` and `paths` on top.
+*/
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub(crate) struct ResolverConfig {
+    pub base_url: Option<PathBuf>,
+    pub paths: Vec<(String, Vec<String>)>,
 }
 
-pub(crate) fn try_resolve_resolver_label(
-    resolver_locs: HashMap<String, String>,
-    entry_id: &String
-) -> HashMap<String, String> {
-    //let file_resolver = resolver::paths_resolver(TargetEnv::Node, alias_map, base_url, paths, true);
+/*
+    Process-wide caches shared by every WalnutHandler.
+    Resolver modules are keyed by canonical path and re-parsed only when their mtime and content change.
+*/
+static FILE_RESOLVERS: OnceLock<Mutex<HashMap<ResolverConfig, Arc<FileResolver>>>> = OnceLock::new();
+static MODULE_LABELS: OnceLock<Mutex<HashMap<PathBuf, CachedModuleLabels>>> = OnceLock::new();
 
-    let file_resolver = get_file_resolver(entry_id);
+struct CachedModuleLabels {
+    modified: Option<SystemTime>,
+    content_hash: u64,
+    // Which packages counted as walnut when the module was scanned.
+    packages: Vec<String>,
+    labels: Arc<ModuleLabels>,
+}
 
-    let mut label_map: HashMap<String, String> = HashMap::new();
+fn module_label_cache() -> &'static Mutex<HashMap<PathBuf, CachedModuleLabels>> {
+    MODULE_LABELS.get_or_init(|| Mutex::new(HashMap::new()))
+}
 
-    for (id, path) in resolver_locs {
-        let module_specifier = &*path;
-        let base = FileName::Real(PathBuf::from(entry_id));
-        let resolved = file_resolver.resolve(&base, module_specifier);
+fn file_resolver_cache() -> &'static Mutex<HashMap<ResolverConfig, Arc<FileResolver>>> {
+    FILE_RESOLVERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+pub(crate) fn get_file_resolver(config: &ResolverConfig) -> Arc<FileResolver> {
+    let mut file_resolvers = file_resolver_cache().lock().unwrap();
 
-        let res_path = match resolved {
-            Ok(res) => {
-                //println!("{:?}", res.filename);
-                res.filename
+    file_resolvers
+        .entry(config.clone())
+        .or_insert_with(|| {
+            let node_resolver = NodeModulesResolver::new(TargetEnv::Node, Default::default(), true);
+
+            let inner: Box<dyn Resolve> = match &config.base_url {
+                Some(base_url) =>
+                    Box::new(TsConfigResolver::new(node_resolver, base_url.clone(), config.paths.clone())),
+                None => Box::new(node_resolver),
+            };
+
+            Arc::new(CachingResolver::new(4096, inner))
+        })
+        .clone()
+}
+
+/*
+    Drops cached resolver modules, either a single file or everything when `path` is None.
+    Resolutions are always dropped since an added or removed file can change where an import points.
+*/
+pub(crate) fn invalidate_resolver_cache(path: Option<String>) {
+    file_resolver_cache().lock().unwrap().clear();
+
+    let mut cache = module_label_cache().lock().unwrap();
+    match path {
+        Some(p) => {
+            let p = PathBuf::from(p);
+            let canonical = fs::canonicalize(&p).unwrap_or(p.clone());
+            cache.remove(&canonical);
+            cache.remove(&p);
+        }
+        None => cache.clear(),
+    }
+}
+
+/*
+    Where a resolver used in the current file was imported from.
+    `name` is the name the resolver is exported under in that module.
+*/
+#[derive(Clone, Debug)]
+pub(crate) struct ResolverLoc {
+    pub src: String,
+    pub name: String,
+}
+
+/*
+    A makeResolver declaration found for a resolver id. `module` is the file declaring it when
+    that's another module, builtins like `file:` are relative to it.
+*/
+#[derive(Clone)]
+pub(crate) struct ResolvedLabel {
+    pub label: String,
+    pub pure: Option<Arc<PureResolver>>,
+    pub module: Option<String>,
+}
+
+/*
+    A makeResolver body simple enough to evaluate at build time:
+    `(key) => expr` or `function (key) { return expr; }`, where expr only uses literals,
+    the key parameter and same-module constants and enums.
+*/
+pub(crate) struct PureResolver {
+    key_param: Option<String>,
+    body: Box<Expr>,
+    statics: Arc<ModuleStatics>,
+}
+
+impl PureResolver {
+    fn from_expr(e: &Expr) -> Option<Self> {
+        let (params, body): (Vec<&Pat>, &Expr) = match e {
+            Expr::Arrow(arrow) => {
+                let body = match &*arrow.body {
+                    ArrowFunctionBody::Expr(e) => &**e,
+                    ArrowFunctionBody::FunctionBody(body) => Self::returned_expr(body)?,
+                };
+                (arrow.params.iter().collect(), body)
+            }
+            Expr::Fn(FnExpr { function, .. }) => {
+                let body = Self::returned_expr(function.body.as_ref()?)?;
+                (function.params.iter().map(|p| &p.pat).collect(), body)
+            }
+            Expr::Paren(ParenExpr { expr, .. }) => {
+                return Self::from_expr(expr);
+            }
+            _ => {
+                return None;
+            }
+        };
+
+        if function_is_async_or_generator(e) {
+            return None;
+        }
+
+        let key_param = match params.as_slice() {
+            [] => None,
+            [Pat::Ident(i)] => Some(String::from(&*i.sym)),
+            _ => {
+                return None;
             }
+        };
+
+        Some(PureResolver {
+            key_param,
+            body: Box::new(body.clone()),
+            statics: Arc::default(),
+        })
+    }
+
+    fn returned_expr(body: &FunctionBody) -> Option<&Expr> {
+        match body.stmts.as_slice() {
+            [Stmt::Return(ReturnStmt { arg: Some(arg), .. })] => Some(&**arg),
+            _ => None,
+        }
+    }
+
+    // Evaluates the body for a single active key, None if it turns out not to be static after all.
+    pub fn eval(&self, walnut_key: &str) -> Option<StaticValue> {
+        let mut scope = StaticScope::new(&self.statics);
+        if let Some(param) = &self.key_param {
+            scope.bindings.insert(param.clone(), StaticValue::Str(String::from(walnut_key)));
+        }
+        scope.eval(&self.body)
+    }
+}
+
+fn function_is_async_or_generator(e: &Expr) -> bool {
+    match e {
+        Expr::Arrow(arrow) => arrow.is_async || arrow.is_generator,
+        Expr::Fn(FnExpr { function, .. }) => function.is_async || function.is_generator,
+        _ => false,
+    }
+}
+
+/*
+    Why a resolver couldn't be traced back to its makeResolver label.
+    `path` is the last module the search got to, if the specifier resolved at all.
+*/
+#[derive(Clone, Debug)]
+pub(crate) struct ResolveFailure {
+    pub specifier: String,
+    pub path: Option<String>,
+    pub error: String,
+}
+
+pub(crate) fn try_resolve_resolver_label(
+    resolver_locs: HashMap<String, ResolverLoc>,
+    entry_id: &String,
+    packages: &[String],
+    config: &ResolverConfig
+) -> HashMap<String, Result<ResolvedLabel, ResolveFailure>> {
+    let file_resolver = get_file_resolver(config);
+
+    let mut label_map: HashMap<String, Result<ResolvedLabel, ResolveFailure>> = HashMap::new();
+
+    for (id, loc) in resolver_locs {
+        let base = FileName::Real(PathBuf::from(entry_id));
+        let mut search = LabelSearch::new(&file_resolver, packages);
+
+        let label = search.find(&base, &loc.src, &loc.name).map_err(|error| ResolveFailure {
+            specifier: loc.src.clone(),
+            path: search.last_path.clone(),
+            error,
+        });
+
+        label_map.insert(id, label);
+    }
+
+    label_map
+}
+
+/*
+    A single walk from an import to the makeResolver declaration behind it.
+*/
+struct LabelSearch<'a> {
+    file_resolver: &'a FileResolver,
+    packages: &'a [String],
+    visited: HashSet<(String, String)>,
+    last_path: Option<String>,
+}
+
+impl<'a> LabelSearch<'a> {
+    fn new(file_resolver: &'a FileResolver, packages: &'a [String]) -> Self {
+        LabelSearch {
+            file_resolver,
+            packages,
+            visited: HashSet::new(),
+            last_path: None,
+        }
+    }
+
+    /*
+        Resolves `module_specifier` from `base` and looks for the resolver exported as `export_name`,
+        following re-exports and barrel files until the makeResolver declaration is reached.
+    */
+    fn find(
+        &mut self,
+        base: &FileName,
+        module_specifier: &str,
+        export_name: &str
+    ) -> Result<ResolvedLabel, String> {
+        let res_path = match self.file_resolver.resolve(base, module_specifier) {
+            Ok(res) => res.filename,
             Err(e) => {
-                println!("{:?}", e);
-                continue;
+                return Err(format!("failed to resolve \"{module_specifier}\" from {base}: {e:?}"));
             }
         };
+        self.last_path = Some(res_path.to_string());
 
-        let search_id = id;
+        // Re-export chains can loop back on themselves, bail out once we've been here.
+        if !self.visited.insert((res_path.to_string(), export_name.to_string())) {
+            return Err(format!("re-export cycle while looking for `{export_name}` in {res_path}"));
+        }
 
-        let label = get_resolver_label(&search_id, res_path);
+        let module = get_module_labels(&res_path, self.packages)?;
 
-        match label {
-            Some(s) => {
-                label_map.insert(search_id, s);
+        match module.exports.get(export_name) {
+            Some(ExportOrigin::Local(local)) => {
+                return self.find_local(&module, &res_path, local);
+            }
+            Some(ExportOrigin::ReExport { src, name }) => {
+                return self.find(&res_path, src, name);
             }
             None => {}
         }
+
+        // `export *` never forwards the default export.
+        if export_name != "default" {
+            for src in module.star_exports.iter() {
+                if let Ok(label) = self.find(&res_path, src, export_name) {
+                    return Ok(label);
+                }
+            }
+        }
+
+        self.last_path = Some(res_path.to_string());
+        Err(format!("no makeResolver exported as `{export_name}` in {res_path}"))
     }
 
-    label_map
+    fn find_local(
+        &mut self,
+        module: &ModuleLabels,
+        res_path: &FileName,
+        local: &String
+    ) -> Result<ResolvedLabel, String> {
+        if let Some(label) = module.resolved(local) {
+            return Ok(ResolvedLabel { module: Some(res_path.to_string()), ..label });
+        }
+
+        // `import { x } from "./y"; export { x as z };`
+        match module.imports.get(local) {
+            Some((src, name)) => self.find(res_path, src, name),
+            None => Err(format!("`{local}` in {res_path} is not a makeResolver call")),
+        }
+    }
+}
+
+/*
+    Follows imports of other modules to walnut, so intrinsics re-exported by a wrapper module work like
+    ones imported from walnut directly:
+        // src/lib/variants.ts
+        export { $Val, $Walnut } from "walnut-ts";
+        // app code
+        import { $Val } from "@/lib/variants";
+    Modules are scanned through the resolver module cache, so each wrapper is only parsed once.
+*/
+pub(crate) struct WalnutReExports {
+    base: FileName,
+    file_resolver: Arc<FileResolver>,
+    packages: Vec<String>,
+}
+
+impl WalnutReExports {
+    pub fn new(entry_id: &str, packages: &[String], config: &ResolverConfig) -> Self {
+        WalnutReExports {
+            base: FileName::Real(PathBuf::from(entry_id)),
+            file_resolver: get_file_resolver(config),
+            packages: packages.to_vec(),
+        }
+    }
+
+    /*
+        The walnut export that `export_name` of `specifier` is, if it is one.
+        `*` stands for the module namespace, `default` for the default export.
+    */
+    pub fn find(&self, specifier: &str, export_name: &str) -> Option<String> {
+        let mut visited: HashSet<(String, String)> = HashSet::new();
+        self.find_from(&self.base, specifier, export_name, &mut visited)
+    }
+
+    fn find_from(
+        &self,
+        base: &FileName,
+        specifier: &str,
+        export_name: &str,
+        visited: &mut HashSet<(String, String)>
+    ) -> Option<String> {
+        if is_walnut_package(specifier, &self.packages) {
+            return Some(String::from(export_name));
+        }
+
+        let res_path = self.file_resolver.resolve(base, specifier).ok()?.filename;
+
+        // Packages are only looked into for names walnut exports, not for every default or namespace import.
+        if in_node_modules(&res_path) && Intrinsic::from_name(export_name).is_none() && export_name != "Walnut" {
+            return None;
+        }
+        if !visited.insert((res_path.to_string(), String::from(export_name))) {
+            return None;
+        }
+
+        let module = get_module_labels(&res_path, &self.packages).ok()?;
+
+        // `export * from "walnut-ts"` makes the whole wrapper a walnut namespace.
+        if export_name == "*" {
+            return module.star_exports
+                .iter()
+                .any(|src| is_walnut_package(src, &self.packages))
+                .then(|| String::from("*"));
+        }
+
+        match module.exports.get(export_name) {
+            Some(ExportOrigin::Local(local)) => {
+                let (src, name) = module.imports.get(local)?;
+                return self.find_from(&res_path, src, name, visited);
+            }
+            Some(ExportOrigin::ReExport { src, name }) => {
+                return self.find_from(&res_path, src, name, visited);
+            }
+            None => {}
+        }
+
+        if export_name == "default" {
+            return None;
+        }
+        module.star_exports.iter().find_map(|src| self.find_from(&res_path, src, export_name, visited))
+    }
 }
 
-fn get_resolver_label(search_id: &String, res_path: FileName) -> Option<String> {
+pub(crate) fn in_node_modules(path: &FileName) -> bool {
+    match path {
+        FileName::Real(p) => Path::new(p).components().any(|c| c == Component::Normal("node_modules".as_ref())),
+        _ => false,
+    }
+}
+
+fn get_module_labels(res_path: &FileName, packages: &[String]) -> Result<Arc<ModuleLabels>, String> {
+    let path = PathBuf::from(res_path.to_string());
+    let canonical = fs::canonicalize(&path).unwrap_or(path);
+    let modified = fs::metadata(&canonical).and_then(|m| m.modified()).ok();
+
+    if modified.is_some() {
+        let cache = module_label_cache().lock().unwrap();
+        if let Some(cached) = cache.get(&canonical) {
+            if cached.modified == modified && cached.packages == packages {
+                return Ok(cached.labels.clone());
+            }
+        }
+    }
+
+    let code = match fs::read_to_string(&canonical) {
+        Ok(code) => code,
+        Err(e) => {
+            return Err(format!("failed to read {}: {e}", canonical.display()));
+        }
+    };
+
+    let mut hasher = DefaultHasher::new();
+    code.hash(&mut hasher);
+    let content_hash = hasher.finish();
+
+    // Touched but unchanged, no need to parse it again.
+    {
+        let mut cache = module_label_cache().lock().unwrap();
+        if let Some(cached) = cache.get_mut(&canonical) {
+            if cached.content_hash == content_hash && cached.packages == packages {
+                cached.modified = modified;
+                return Ok(cached.labels.clone());
+            }
+        }
+    }
+
     let cm = Lrc::<SourceMap>::default();
     let handler = Handler::with_tty_emitter(ColorConfig::Auto, true, false, Some(cm.clone()));
     let compiler = swc::Compiler::new(cm.clone());
 
-    let fm = cm.load_file(Path::new(&res_path.to_string())).expect("Something gone wrong.");
+    let fm = cm.new_source_file(Lrc::new(FileName::Real(canonical.clone())), code);
 
     let program = compiler
         .parse_js(
             fm,
             &handler,
             EsVersion::Es2020,
-            Syntax::Typescript(TsConfig {
+            Syntax::Typescript(TsSyntax {
                 tsx: true,
                 decorators: false,
                 dts: false,
@@ -93,92 +468,545 @@ fn get_resolver_label(search_id: &String, res_path: FileName) -> Option<String>
             swc::config::IsModule::Bool(true),
             Default::default()
         )
-        .expect("Failed to parse");
+        .map_err(|e| format!("failed to parse {}: {e}", canonical.display()))?;
+
+    let labels = Arc::new(scan_module_labels(&program, packages));
+
+    module_label_cache().lock().unwrap().insert(canonical, CachedModuleLabels {
+        modified,
+        content_hash,
+        packages: packages.to_vec(),
+        labels: labels.clone(),
+    });
 
-    let mut label_finder = ResLabelFinder::new(search_id.clone());
+    Ok(labels)
+}
+
+pub(crate) fn scan_module_labels(program: &Program, packages: &[String]) -> ModuleLabels {
+    let mut names = MakeResolverNames::new(packages);
+    program.visit_with(&mut names);
 
-    program.visit_with(&mut label_finder);
+    let mut label_scanner = ModuleLabelScanner::new(&names);
+    program.visit_with(&mut label_scanner);
 
-    label_finder.label
+    label_scanner.finish(Arc::new(ModuleStatics::collect(program)))
 }
 
-struct ResLabelFinder {
-    search_id: String,
-    label: Option<String>,
+fn export_name_to_string(name: &ModuleExportName) -> String {
+    match name {
+        ModuleExportName::Ident(i) => String::from(&*i.sym),
+        ModuleExportName::Str(s) => s.value.to_string_lossy().into_owned(),
+    }
 }
 
-impl ResLabelFinder {
-    pub fn new(search_id: String) -> Self {
-        ResLabelFinder {
-            search_id,
-            label: None,
+// A string label, either a plain literal or a template literal without expressions.
+pub(crate) fn static_string(e: &Expr) -> Option<String> {
+    match e {
+        Expr::Lit(Lit::Str(s)) => Some(s.value.to_string_lossy().into_owned()),
+        Expr::Tpl(tpl) if tpl.exprs.is_empty() && tpl.quasis.len() == 1 => {
+            let q = &tpl.quasis[0];
+            match &q.cooked {
+                Some(cooked) => Some(cooked.to_string_lossy().into_owned()),
+                None => Some(String::from(&*q.raw)),
+            }
         }
+        _ => None,
     }
 }
 
-impl Visit for ResLabelFinder {
-    fn visit_var_declarators(&mut self, n: &[VarDeclarator]) {
-        for dec in n {
-            let name = match dec.name.clone() {
-                Pat::Ident(s) => String::from(&*s.sym),
-                _ => {
-                    return;
+/*
+    The CommonJS export written to by an assignment, if any.
+    `module.exports = ...` is the default export, `exports.x = ...` and `module.exports.x = ...` export `x`.
+*/
+fn cjs_export_name(left: &AssignTarget) -> Option<String> {
+    let AssignTarget::Simple(SimpleAssignTarget::Member(MemberExpr { obj, prop: MemberProp::Ident(prop), .. })) = left else {
+        return None;
+    };
+
+    match &**obj {
+        Expr::Ident(i) if &*i.sym == "module" && &*prop.sym == "exports" => {
+            Some(String::from("default"))
+        }
+        Expr::Ident(i) if &*i.sym == "exports" => Some(String::from(&*prop.sym)),
+        Expr::Member(
+            MemberExpr { obj: inner_obj, prop: MemberProp::Ident(inner_prop), .. },
+        ) if &*inner_prop.sym == "exports" => {
+            match &**inner_obj {
+                Expr::Ident(i) if &*i.sym == "module" => Some(String::from(&*prop.sym)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+enum ExportOrigin {
+    // Exported from a binding in the module itself.
+    Local(String),
+    // `export { name } from "src"`
+    ReExport {
+        src: String,
+        name: String,
+    },
+}
+
+/*
+    Everything in a module that matters for finding a resolver label:
+    resolver declarations, imports that may be re-exported and the module's exports.
+    Resolvers that are only reachable through an export (`export default ...`, `exports.x = ...`)
+    are stored under a local name that can't clash with a binding, `default` or `exports.x`.
+    `declared` holds the top-level bindings resolvers are declared as, `imported` every import binding.
+*/
+#[derive(Default)]
+pub(crate) struct ModuleLabels {
+    pub labels: HashMap<String, String>,
+    pub declared: HashSet<Id>,
+    pub imported: HashSet<Id>,
+    pure_resolvers: HashMap<String, Arc<PureResolver>>,
+    imports: HashMap<String, (String, String)>,
+    exports: HashMap<String, ExportOrigin>,
+    star_exports: Vec<String>,
+}
+
+impl ModuleLabels {
+    pub fn resolved(&self, local: &str) -> Option<ResolvedLabel> {
+        Some(ResolvedLabel {
+            label: self.labels.get(local)?.clone(),
+            pure: self.pure_resolvers.get(local).cloned(),
+            module: None,
+        })
+    }
+
+    pub fn is_exported(&self, local: &str) -> bool {
+        self.exports.values().any(|origin| matches!(origin, ExportOrigin::Local(l) if l == local))
+    }
+}
+
+/*
+    Collects the names makeResolver (and builtin) can be reached through in a module:
+    `import { makeResolver as mk }`, `import * as W`, `const { makeResolver } = require(...)` and so on.
+    `Walnut.makeResolver` is always accepted.
+*/
+struct MakeResolverNames {
+    packages: Vec<String>,
+    fns: HashSet<String>,
+    builtin_fns: HashSet<String>,
+    namespaces: HashSet<String>,
+}
+
+impl MakeResolverNames {
+    pub fn new(packages: &[String]) -> Self {
+        MakeResolverNames {
+            packages: packages.to_vec(),
+            fns: HashSet::new(),
+            builtin_fns: HashSet::new(),
+            namespaces: HashSet::from([String::from("Walnut")]),
+        }
+    }
+
+    fn add_imported(&mut self, imported: &str, local: &str) {
+        match imported {
+            "makeResolver" => {
+                self.fns.insert(String::from(local));
+            }
+            "builtin" => {
+                self.builtin_fns.insert(String::from(local));
+            }
+            "Walnut" | "default" => {
+                self.namespaces.insert(String::from(local));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Visit for MakeResolverNames {
+    fn visit_import_decl(&mut self, n: &ImportDecl) {
+        if !is_walnut_package(&n.src.value.to_string_lossy(), &self.packages) {
+            return;
+        }
+
+        for spec in n.specifiers.iter() {
+            match spec {
+                ImportSpecifier::Named(s) => {
+                    let imported = match &s.imported {
+                        Some(name) => export_name_to_string(name),
+                        None => String::from(&*s.local.sym),
+                    };
+                    self.add_imported(&imported, &s.local.sym);
+                }
+                ImportSpecifier::Default(s) => {
+                    self.namespaces.insert(String::from(&*s.local.sym));
+                }
+                ImportSpecifier::Namespace(s) => {
+                    self.namespaces.insert(String::from(&*s.local.sym));
+                }
+            }
+        }
+    }
+
+    fn visit_var_declarator(&mut self, n: &VarDeclarator) {
+        let Some(init) = &n.init else {
+            return;
+        };
+        if walnut_require_source(init, &self.packages).is_none() {
+            n.visit_children_with(self);
+            return;
+        }
+
+        match &n.name {
+            Pat::Ident(s) => {
+                self.namespaces.insert(String::from(&*s.sym));
+            }
+            Pat::Object(obj) => {
+                for prop in obj.props.iter() {
+                    match prop {
+                        ObjectPatProp::KeyValue(KeyValuePatProp { key, value }) => {
+                            let imported = match key {
+                                PropName::Ident(i) => String::from(&*i.sym),
+                                PropName::Str(s) => s.value.to_string_lossy().into_owned(),
+                                _ => {
+                                    continue;
+                                }
+                            };
+                            if let Pat::Ident(local) = &**value {
+                                self.add_imported(&imported, &local.sym);
+                            }
+                        }
+                        ObjectPatProp::Assign(AssignPatProp { key, .. }) => {
+                            self.add_imported(&key.sym, &key.sym);
+                        }
+                        ObjectPatProp::Rest(_) => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/*
+    Walks a module filling out its ModuleLabels.
+    Labels given as a `const` binding are resolved once the whole module has been seen.
+*/
+struct ModuleLabelScanner<'a> {
+    names: &'a MakeResolverNames,
+    module: ModuleLabels,
+    label_consts: HashMap<String, String>,
+    consts: HashMap<String, String>,
+    pure_bodies: HashMap<String, PureResolver>,
+}
+
+impl<'a> ModuleLabelScanner<'a> {
+    pub fn new(names: &'a MakeResolverNames) -> Self {
+        ModuleLabelScanner {
+            names,
+            module: ModuleLabels::default(),
+            label_consts: HashMap::new(),
+            consts: HashMap::new(),
+            pure_bodies: HashMap::new(),
+        }
+    }
+
+    fn finish(mut self, statics: Arc<ModuleStatics>) -> ModuleLabels {
+        for (local, const_name) in self.label_consts {
+            if let Some(label) = self.consts.get(&const_name) {
+                self.module.labels.insert(local, label.clone());
+            }
+        }
+
+        for (local, mut pure) in self.pure_bodies {
+            pure.statics = statics.clone();
+            self.module.pure_resolvers.insert(local, Arc::new(pure));
+        }
+
+        self.module
+    }
+
+    fn add_resolver(&mut self, local: String, e: &Expr) -> bool {
+        let mut v = LabelExtractor::new(self.names);
+        e.visit_with(&mut v);
+
+        if let Some(pure) = v.body.as_deref().and_then(PureResolver::from_expr) {
+            self.pure_bodies.insert(local.clone(), pure);
+        }
+
+        match v.res {
+            Some(LabelArg::Value(label)) => {
+                self.module.labels.insert(local, label);
+                true
+            }
+            Some(LabelArg::Const(const_name)) => {
+                self.label_consts.insert(local, const_name);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Resolvers and label consts are only picked up at the top level, a nested binding of the
+    // same name is something else.
+    fn add_top_level_var(&mut self, var: &VarDecl) {
+        for dec in var.decls.iter() {
+            let (Pat::Ident(s), Some(init)) = (&dec.name, &dec.init) else {
+                continue;
+            };
+            let name = String::from(&*s.sym);
+
+            if var.kind == VarDeclKind::Const {
+                if let Some(value) = static_string(init) {
+                    self.consts.insert(name.clone(), value);
+                }
+            }
+            if self.add_resolver(name, init) {
+                self.module.declared.insert(s.to_id());
+            }
+        }
+    }
+
+    fn add_cjs_export(&mut self, name: String, e: &Expr) {
+        if let Expr::Ident(i) = e {
+            self.module.exports.insert(name, ExportOrigin::Local(String::from(&*i.sym)));
+            return;
+        }
+
+        let local = match name.as_str() {
+            "default" => name.clone(),
+            _ => format!("exports.{name}"),
+        };
+        if self.add_resolver(local.clone(), e) {
+            self.module.exports.insert(name, ExportOrigin::Local(local));
+        }
+    }
+}
+
+impl<'a> Visit for ModuleLabelScanner<'a> {
+    fn visit_import_decl(&mut self, n: &ImportDecl) {
+        let src = n.src.value.to_string_lossy().into_owned();
+
+        for spec in n.specifiers.iter() {
+            let local = match spec {
+                ImportSpecifier::Named(s) => &s.local,
+                ImportSpecifier::Default(s) => &s.local,
+                ImportSpecifier::Namespace(s) => &s.local,
+            };
+            self.module.imported.insert(local.to_id());
+
+            match spec {
+                ImportSpecifier::Named(s) => {
+                    let imported = match &s.imported {
+                        Some(name) => export_name_to_string(name),
+                        None => String::from(&*s.local.sym),
+                    };
+                    self.module.imports.insert(String::from(&*s.local.sym), (src.clone(), imported));
+                }
+                ImportSpecifier::Default(s) => {
+                    self.module.imports.insert(
+                        String::from(&*s.local.sym),
+                        (src.clone(), String::from("default"))
+                    );
                 }
+                // A namespace can't be a resolver itself, but it can be walnut re-exported.
+                ImportSpecifier::Namespace(s) => {
+                    self.module.imports.insert(String::from(&*s.local.sym), (src.clone(), String::from("*")));
+                }
+            }
+        }
+    }
+
+    fn visit_export_decl(&mut self, n: &ExportDecl) {
+        if let Decl::Var(var) = &n.decl {
+            for dec in var.decls.iter() {
+                if let Pat::Ident(s) = &dec.name {
+                    let name = String::from(&*s.sym);
+                    self.module.exports.insert(name.clone(), ExportOrigin::Local(name));
+                }
+            }
+        }
+
+        n.visit_children_with(self);
+    }
+
+    fn visit_named_export(&mut self, n: &NamedExport) {
+        for spec in n.specifiers.iter() {
+            // `export * as W from "walnut-ts"`
+            if let (ExportSpecifier::Namespace(s), Some(src)) = (spec, &n.src) {
+                self.module.exports.insert(export_name_to_string(&s.name), ExportOrigin::ReExport {
+                    src: src.value.to_string_lossy().into_owned(),
+                    name: String::from("*"),
+                });
+                continue;
+            }
+            let ExportSpecifier::Named(s) = spec else {
+                continue;
             };
 
-            if name == self.search_id {
-                let mut v = LabelExtractor::new();
-                n.visit_children_with(&mut v);
+            let orig = export_name_to_string(&s.orig);
+            let exported = match &s.exported {
+                Some(name) => export_name_to_string(name),
+                None => orig.clone(),
+            };
+
+            let origin = match &n.src {
+                Some(src) => ExportOrigin::ReExport { src: src.value.to_string_lossy().into_owned(), name: orig },
+                None => ExportOrigin::Local(orig),
+            };
 
-                self.label = v.res;
+            self.module.exports.insert(exported, origin);
+        }
+    }
+
+    fn visit_export_all(&mut self, n: &ExportAll) {
+        self.module.star_exports.push(n.src.value.to_string_lossy().into_owned());
+    }
+
+    fn visit_export_default_expr(&mut self, n: &ExportDefaultExpr) {
+        self.add_cjs_export(String::from("default"), &n.expr);
+    }
+
+    fn visit_assign_expr(&mut self, n: &AssignExpr) {
+        if n.op != AssignOp::Assign {
+            return;
+        }
+        let Some(name) = cjs_export_name(&n.left) else {
+            return;
+        };
+
+        match &*n.right {
+            // module.exports = { a, b: makeResolver("b") }
+            Expr::Object(obj) if name == "default" => {
+                for prop in obj.props.iter() {
+                    let PropOrSpread::Prop(prop) = prop else {
+                        continue;
+                    };
+                    match &**prop {
+                        Prop::Shorthand(i) => {
+                            let local = String::from(&*i.sym);
+                            self.module.exports.insert(local.clone(), ExportOrigin::Local(local));
+                        }
+                        Prop::KeyValue(KeyValueProp { key, value }) => {
+                            let export_name = match key {
+                                PropName::Ident(i) => String::from(&*i.sym),
+                                PropName::Str(s) => s.value.to_string_lossy().into_owned(),
+                                _ => {
+                                    continue;
+                                }
+                            };
+                            self.add_cjs_export(export_name, value);
+                        }
+                        _ => {}
+                    }
+                }
             }
+            right => self.add_cjs_export(name, right),
         }
     }
+
+    fn visit_module(&mut self, n: &Module) {
+        for item in n.body.iter() {
+            match item {
+                ModuleItem::Stmt(Stmt::Decl(Decl::Var(var))) => self.add_top_level_var(var),
+                ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(ExportDecl { decl: Decl::Var(var), .. })) => {
+                    self.add_top_level_var(var);
+                }
+                _ => {}
+            }
+        }
+
+        n.visit_children_with(self);
+    }
+
+    fn visit_script(&mut self, n: &Script) {
+        for stmt in n.body.iter() {
+            if let Stmt::Decl(Decl::Var(var)) = stmt {
+                self.add_top_level_var(var);
+            }
+        }
+
+        n.visit_children_with(self);
+    }
+}
+
+enum LabelArg {
+    Value(String),
+    // The name of a `const` holding the label.
+    Const(String),
 }
 
-struct LabelExtractor {
-    res: Option<String>,
+struct LabelExtractor<'a> {
+    names: &'a MakeResolverNames,
+    res: Option<LabelArg>,
+    // The second makeResolver argument, the resolver body.
+    body: Option<Box<Expr>>,
 }
 
-impl LabelExtractor {
-    pub fn new() -> Self {
+impl<'a> LabelExtractor<'a> {
+    pub fn new(names: &'a MakeResolverNames) -> Self {
         LabelExtractor {
+            names,
             res: None,
+            body: None,
         }
     }
 
-    fn is_valid_identifier(e: &Expr) -> bool {
+    fn callee_name(&self, e: &Expr) -> Option<&'static str> {
         match e {
+            Expr::Ident(i) if self.names.fns.contains(&*i.sym) => Some("makeResolver"),
+            Expr::Ident(i) if self.names.builtin_fns.contains(&*i.sym) => Some("builtin"),
             Expr::Member(MemberExpr { obj, prop: MemberProp::Ident(prop), .. }) =>
                 match &**obj {
-                    Expr::Ident(i) => &*i.sym == "Walnut" && &*prop.sym == "makeResolver",
-                    _ => false,
+                    Expr::Ident(i) if self.names.namespaces.contains(&*i.sym) =>
+                        match &*prop.sym {
+                            "makeResolver" => Some("makeResolver"),
+                            "builtin" => Some("builtin"),
+                            _ => None,
+                        }
+                    _ => None,
                 }
-            _ => false,
+            _ => None,
+        }
+    }
+
+    // `Walnut.builtin("env", "API_URL")` is shorthand for the `env:API_URL` label.
+    fn builtin_label(n: &CallExpr) -> Option<String> {
+        let kind = static_string(&n.args.first()?.expr)?;
+
+        match n.args.get(1) {
+            Some(arg) => Some(format!("{kind}:{}", static_string(&arg.expr)?)),
+            None => Some(kind),
         }
     }
 }
 
-impl Visit for LabelExtractor {
+impl<'a> Visit for LabelExtractor<'a> {
     fn visit_call_expr(&mut self, n: &CallExpr) {
-        match &n.callee {
-            Callee::Expr(callee) if Self::is_valid_identifier(callee) => {}
-            _ => {
-                return;
-            }
-        }
+        let callee_name = match &n.callee {
+            Callee::Expr(callee) => self.callee_name(callee),
+            _ => None,
+        };
 
-        let label = match n.args.get(0) {
-            Some(v) =>
-                match *v.expr.clone() {
-                    Expr::Lit(lit) =>
-                        match lit {
-                            Lit::Str(s) => String::from(&*s.value),
-                            _ => {
-                                return;
-                            }
+        let label = match callee_name {
+            Some("builtin") =>
+                match Self::builtin_label(n) {
+                    Some(s) => LabelArg::Value(s),
+                    None => {
+                        return;
+                    }
+                }
+            Some(_) =>
+                match n.args.first() {
+                    Some(v) =>
+                        match &*v.expr {
+                            Expr::Ident(i) => LabelArg::Const(String::from(&*i.sym)),
+                            e =>
+                                match static_string(e) {
+                                    Some(s) => LabelArg::Value(s),
+                                    None => {
+                                        return;
+                                    }
+                                }
                         }
-                    _ => {
+                    None => {
                         return;
                     }
                 }
@@ -187,6 +1015,10 @@ impl Visit for LabelExtractor {
             }
         };
 
+        if callee_name == Some("makeResolver") {
+            self.body = n.args.get(1).map(|arg| arg.expr.clone());
+        }
+
         self.res = Some(label);
     }
 }
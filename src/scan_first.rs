@@ -1,57 +1,275 @@
 #![deny(clippy::all)]
 
+use std::collections::HashSet;
 use swc_ecma_ast::*;
-use swc_ecma_visit::VisitMut;
-use swc_common::util::take::Take;
+use swc_ecma_visit::{ Visit, VisitWith };
 
-use crate::transform::WalnutSymbols;
+use crate::transform::{ Intrinsic, WalnutBindings, WalnutSymbols };
+use crate::helpers::{ jsx_element_name, walnut_require_source };
+use crate::options::{ is_walnut_package, WalnutOptions };
+use crate::resolver::WalnutReExports;
+
+// Variant props are plain attributes, `<Button walnut:ios={...}>` and `$props`, nothing is imported for them.
+const VARIANT_PROPS: [&str; 2] = ["walnut:", "$props"];
+
+/*
+    A plain text check that runs before parsing. A file that mentions neither a walnut package, an
+    intrinsic, variant props nor a configured component name can't be using walnut. Wrapper modules
+    are covered by the intrinsic names, only a wrapper that renames every intrinsic gets past this.
+*/
+pub(crate) fn might_contain_walnut(code: &str, options: &WalnutOptions) -> bool {
+    let intrinsics = [WalnutSymbols::VAL, WalnutSymbols::PVAL, WalnutSymbols::RESOLVE, WalnutSymbols::WALNUT];
+
+    intrinsics.iter().any(|name| code.contains(name)) ||
+        VARIANT_PROPS.iter().any(|p| code.contains(p)) ||
+        options.packages().iter().any(|p| code.contains(p.as_str())) ||
+        options.jsx_names().iter().any(|n| code.contains(n.as_str()))
+}
 
 pub(crate) struct ScanFirst {
     pub should_run: bool,
     pub run_val: bool,
     pub run_resolve: bool,
     pub run_jsx: bool,
+    pub bindings: WalnutBindings,
+    // Bindings that can go once nothing refers to them anymore: every specifier of a walnut
+    // import, along with intrinsics brought in through requires and wrapper modules.
+    pub removable: HashSet<Id>,
+    // The import sources that matched a walnut package.
+    pub sources: Vec<String>,
+    packages: Vec<String>,
+    jsx_names: Vec<String>,
+    re_exports: WalnutReExports,
 }
 
 impl ScanFirst {
-    pub fn new() -> Self {
+    pub fn new(packages: Vec<String>, jsx_names: Vec<String>, re_exports: WalnutReExports) -> Self {
         ScanFirst {
             should_run: false,
             run_val: false,
             run_resolve: false,
             run_jsx: false,
+            bindings: WalnutBindings::new(),
+            removable: HashSet::new(),
+            sources: Vec::new(),
+            packages,
+            jsx_names,
+            re_exports,
         }
     }
 
     fn check_if_walnut_import(&mut self, src: &str) -> bool {
-        if src.contains("walnut-ts") {
-            return true;
+        if !is_walnut_package(src, &self.packages) {
+            return false;
         }
-        false
+        if !self.sources.iter().any(|s| s == src) {
+            self.sources.push(String::from(src));
+        }
+        true
     }
-}
 
-impl VisitMut for ScanFirst {
-    fn visit_mut_import_decl(&mut self, n: &mut ImportDecl) {
-        if self.check_if_walnut_import(&*n.src.value) {
-            for spec in n.specifiers.iter() {
-                if let Some(s) = spec.as_named() {
-                    match &*s.local.sym {
-                        WalnutSymbols::VAL | WalnutSymbols::PVAL => {
-                            self.run_val = true;
+    fn add_binding(&mut self, id: Id, intrinsic: Intrinsic) {
+        match intrinsic {
+            Intrinsic::Val | Intrinsic::PVal => {
+                self.run_val = true;
+            }
+            Intrinsic::Resolve => {
+                self.run_resolve = true;
+            }
+            Intrinsic::Walnut => {
+                self.run_jsx = true;
+            }
+            Intrinsic::Namespace => {
+                self.run_val = true;
+                self.run_resolve = true;
+                self.run_jsx = true;
+            }
+        }
+        self.bindings.insert(id.clone(), intrinsic);
+        self.removable.insert(id);
+        self.should_run = true;
+    }
+
+    /*
+        Picks up the intrinsics of a top level `require` of walnut:
+            const { $Val, $Resolve: res } = require("walnut-ts");
+            const W = require("walnut-ts");
+    */
+    fn scan_walnut_require(&mut self, n: &VarDeclarator) {
+        let Some(src) = n.init.as_deref().and_then(|init| walnut_require_source(init, &self.packages)) else {
+            return;
+        };
+        self.check_if_walnut_import(&src);
+
+        match &n.name {
+            Pat::Ident(ns) => {
+                self.add_binding(ns.to_id(), Intrinsic::Namespace);
+            }
+            Pat::Object(obj) => {
+                for prop in obj.props.iter() {
+                    let (imported, local) = match prop {
+                        ObjectPatProp::KeyValue(KeyValuePatProp { key, value }) => {
+                            let imported = match key {
+                                PropName::Ident(i) => String::from(&*i.sym),
+                                PropName::Str(s) => s.value.to_string_lossy().into_owned(),
+                                _ => {
+                                    continue;
+                                }
+                            };
+                            let Pat::Ident(local) = &**value else {
+                                continue;
+                            };
+                            (imported, local.to_id())
                         }
-                        WalnutSymbols::RESOLVE => {
-                            self.run_resolve = true;
+                        ObjectPatProp::Assign(AssignPatProp { key, value: None, .. }) => {
+                            (String::from(&*key.sym), key.to_id())
                         }
-                        "$Walnut" => {
-                            self.run_jsx = true;
+                        _ => {
+                            continue;
                         }
-                        _ => {}
+                    };
+                    if let Some(intrinsic) = Intrinsic::from_export(&imported) {
+                        self.add_binding(local, intrinsic);
                     }
                 }
             }
-            n.take();
+            _ => {}
+        }
+    }
+
+    // Picks up the intrinsics imported from a module that re-exports walnut.
+    fn scan_re_exported(&mut self, n: &ImportDecl) {
+        if n.type_only || n.specifiers.is_empty() {
+            return;
+        }
+
+        let src = n.src.value.to_string_lossy().into_owned();
+        let mut found = false;
+
+        for spec in n.specifiers.iter() {
+            let (local, export_name) = match spec {
+                ImportSpecifier::Named(s) => {
+                    let imported = match &s.imported {
+                        Some(ModuleExportName::Ident(i)) => String::from(&*i.sym),
+                        Some(ModuleExportName::Str(s)) => s.value.to_string_lossy().into_owned(),
+                        None => String::from(&*s.local.sym),
+                    };
+                    (s.local.to_id(), imported)
+                }
+                ImportSpecifier::Namespace(s) => (s.local.to_id(), String::from("*")),
+                ImportSpecifier::Default(s) => (s.local.to_id(), String::from("default")),
+            };
+
+            let intrinsic = self.re_exports
+                .find(&src, &export_name)
+                .and_then(|name| Intrinsic::from_export(&name));
+            if let Some(intrinsic) = intrinsic {
+                self.add_binding(local, intrinsic);
+                found = true;
+            }
+        }
+
+        if found && !self.sources.contains(&src) {
+            self.sources.push(src);
+        }
+    }
+
+    // A configured component, or its `.Else` / `.Switch`.
+    fn is_configured_jsx_name(&self, name: &str) -> bool {
+        self.jsx_names.iter().any(|n| {
+            name == n ||
+                matches!(name.strip_prefix(n.as_str()), Some(".Else") | Some(".Switch"))
+        })
+    }
+}
+
+impl Visit for ScanFirst {
+    fn visit_import_decl(&mut self, n: &ImportDecl) {
+        if !self.check_if_walnut_import(&n.src.value.to_string_lossy()) {
+            self.scan_re_exported(n);
+            return;
+        }
+
+        for spec in n.specifiers.iter() {
+            match spec {
+                ImportSpecifier::Named(s) => {
+                    // Whatever else comes from walnut (makeResolver, builtin) goes too when unused.
+                    self.removable.insert(s.local.to_id());
+
+                    // `import { $Val as pick }` is still $Val.
+                    let imported = match &s.imported {
+                        Some(ModuleExportName::Ident(i)) => String::from(&*i.sym),
+                        Some(ModuleExportName::Str(s)) => s.value.to_string_lossy().into_owned(),
+                        None => String::from(&*s.local.sym),
+                    };
+                    // `import { Walnut }` is the same object as the default export.
+                    let Some(intrinsic) = Intrinsic::from_export(&imported) else {
+                        continue;
+                    };
+
+                    self.add_binding(s.local.to_id(), intrinsic);
+                }
+                // `import * as W` and `import W`, used as `W.$Val(...)` and `<W.$Walnut>`.
+                ImportSpecifier::Namespace(s) => {
+                    self.add_binding(s.local.to_id(), Intrinsic::Namespace);
+                }
+                ImportSpecifier::Default(s) => {
+                    self.add_binding(s.local.to_id(), Intrinsic::Namespace);
+                }
+            }
+        }
+        self.should_run = true;
+    }
+
+    // Only top level requires count, like imports. `"use strict"` directives are plain statements here.
+    fn visit_module_items(&mut self, items: &[ModuleItem]) {
+        items.visit_children_with(self);
+
+        for item in items.iter() {
+            if let ModuleItem::Stmt(Stmt::Decl(Decl::Var(var))) = item {
+                for decl in var.decls.iter() {
+                    self.scan_walnut_require(decl);
+                }
+            }
+        }
+    }
+
+    // Configured component names don't need a walnut import, they may come from anywhere.
+    fn visit_jsx_element_name(&mut self, n: &JSXElementName) {
+        if !self.jsx_names.is_empty() && self.is_configured_jsx_name(&jsx_element_name(n)) {
+            self.run_jsx = true;
+            self.should_run = true;
+        }
+    }
+
+    fn visit_jsx_attr_name(&mut self, n: &JSXAttrName) {
+        let is_variant_prop = match n {
+            JSXAttrName::JSXNamespacedName(n) => &*n.ns.sym == "walnut",
+            JSXAttrName::Ident(i) => &*i.sym == "$props",
+        };
+        if is_variant_prop {
+            self.run_jsx = true;
             self.should_run = true;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_check_looks_for_configured_packages_and_components() {
+        let options = WalnutOptions {
+            packages: Some(vec![String::from(" @app/variants/ ")]),
+            jsx_names: Some(vec![String::from("Platform")]),
+            ..Default::default()
+        };
+
+        assert!(might_contain_walnut("import x from \"@app/variants\";", &options));
+        assert!(might_contain_walnut("<Platform ios />", &options));
+        assert!(might_contain_walnut("<View walnut:ios={{}} />", &options));
+        assert!(!might_contain_walnut("import x from \"walnut-ts\";", &options));
+        assert!(might_contain_walnut("import x from \"walnut-ts\";", &WalnutOptions::default()));
+    }
+}
@@ -0,0 +1,484 @@
+#![deny(clippy::all)]
+
+use std::collections::HashMap;
+use swc_ecma_ast::*;
+
+/*
+    A value known at build time. Produced by evaluating literals, objects, arrays, template literals
+    and references to same-module constants and enums.
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum StaticValue {
+    Str(String),
+    Num(f64),
+    Bool(bool),
+    Null,
+    Undefined,
+    Array(Vec<StaticValue>),
+    Object(Vec<(String, StaticValue)>),
+}
+
+impl StaticValue {
+    // The value as JS source, ready to be inlined in place of an expression.
+    pub fn to_js(&self) -> String {
+        match self {
+            StaticValue::Str(s) => serde_json::Value::String(s.clone()).to_string(),
+            StaticValue::Num(n) => format_num(*n),
+            StaticValue::Bool(b) => b.to_string(),
+            StaticValue::Null => String::from("null"),
+            StaticValue::Undefined => String::from("undefined"),
+            StaticValue::Array(items) => {
+                let items: Vec<String> = items.iter().map(|v| v.to_js()).collect();
+                format!("[{}]", items.join(", "))
+            }
+            StaticValue::Object(props) => {
+                let props: Vec<String> = props
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", serde_json::Value::String(k.clone()), v.to_js()))
+                    .collect();
+                format!("({{ {} }})", props.join(", "))
+            }
+        }
+    }
+
+    // String conversion the way JS does it for template literals and property keys.
+    pub fn to_js_string(&self) -> String {
+        match self {
+            StaticValue::Str(s) => s.clone(),
+            StaticValue::Num(n) => format_num(*n),
+            StaticValue::Bool(b) => b.to_string(),
+            StaticValue::Null => String::from("null"),
+            StaticValue::Undefined => String::from("undefined"),
+            StaticValue::Array(items) => {
+                let items: Vec<String> = items
+                    .iter()
+                    .map(|v| {
+                        match v {
+                            StaticValue::Null | StaticValue::Undefined => String::new(),
+                            v => v.to_js_string(),
+                        }
+                    })
+                    .collect();
+                items.join(",")
+            }
+            StaticValue::Object(_) => String::from("[object Object]"),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<StaticValue> {
+        match self {
+            StaticValue::Object(props) =>
+                props
+                    .iter()
+                    .rev()
+                    .find(|(k, _)| k == key)
+                    .map(|(_, v)| v.clone()),
+            StaticValue::Array(items) => {
+                if key == "length" {
+                    return Some(StaticValue::Num(items.len() as f64));
+                }
+                items.get(key.parse::<usize>().ok()?).cloned()
+            }
+            StaticValue::Str(s) if key == "length" => Some(StaticValue::Num(s.chars().count() as f64)),
+            _ => None,
+        }
+    }
+}
+
+fn format_num(n: f64) -> String {
+    if n.is_nan() {
+        return String::from("NaN");
+    }
+    if n.is_infinite() {
+        return String::from(if n > 0.0 { "Infinity" } else { "-Infinity" });
+    }
+    // -0 prints as 0 in JS.
+    if n == 0.0 {
+        return String::from("0");
+    }
+    // JS switches to exponent notation outside this range, Rust never does on its own.
+    if n.abs() >= 1e21 || n.abs() < 1e-6 {
+        let exp = format!("{n:e}");
+        return match exp.split_once('e') {
+            Some((mantissa, e)) if !e.starts_with('-') => format!("{mantissa}e+{e}"),
+            _ => exp,
+        };
+    }
+    // Shortest round trip digits, without a trailing `.0` for integers.
+    n.to_string()
+}
+
+/*
+    What a module declares at its top level that can be evaluated statically:
+    `const` declarations (evaluated on demand) and TS enums (evaluated up front).
+*/
+#[derive(Default)]
+pub(crate) struct ModuleStatics {
+    consts: HashMap<String, Expr>,
+    enums: HashMap<String, StaticValue>,
+}
+
+impl ModuleStatics {
+    pub fn collect(program: &Program) -> Self {
+        let mut statics = ModuleStatics::default();
+
+        let stmts: Vec<&Stmt> = match program {
+            Program::Module(m) =>
+                m.body
+                    .iter()
+                    .filter_map(|item| {
+                        match item {
+                            ModuleItem::Stmt(stmt) => Some(stmt),
+                            _ => None,
+                        }
+                    })
+                    .collect(),
+            Program::Script(s) => s.body.iter().collect(),
+        };
+        let exported: Vec<&Decl> = match program {
+            Program::Module(m) =>
+                m.body
+                    .iter()
+                    .filter_map(|item| {
+                        match item {
+                            ModuleItem::ModuleDecl(ModuleDecl::ExportDecl(ExportDecl { decl, .. })) => Some(decl),
+                            _ => None,
+                        }
+                    })
+                    .collect(),
+            Program::Script(_) => Vec::new(),
+        };
+
+        let decls = stmts
+            .into_iter()
+            .filter_map(|stmt| {
+                match stmt {
+                    Stmt::Decl(decl) => Some(decl),
+                    _ => None,
+                }
+            })
+            .chain(exported);
+
+        for decl in decls {
+            match decl {
+                Decl::Var(var) if var.kind == VarDeclKind::Const => {
+                    for dec in var.decls.iter() {
+                        if let (Pat::Ident(s), Some(init)) = (&dec.name, &dec.init) {
+                            statics.consts.insert(String::from(&*s.sym), *init.clone());
+                        }
+                    }
+                }
+                Decl::TsEnum(e) => {
+                    if let Some(value) = statics.eval_enum(e) {
+                        statics.enums.insert(String::from(&*e.id.sym), value);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        statics
+    }
+
+    // Enum members in declaration order, numbers auto-increment from the previous member.
+    fn eval_enum(&self, e: &TsEnumDecl) -> Option<StaticValue> {
+        let mut members: Vec<(String, StaticValue)> = Vec::new();
+        let mut next_num: Option<f64> = Some(0.0);
+
+        for member in e.members.iter() {
+            let name = match &member.id {
+                TsEnumMemberId::Ident(i) => String::from(&*i.sym),
+                TsEnumMemberId::Str(s) => s.value.to_string_lossy().into_owned(),
+            };
+
+            let value = match &member.init {
+                Some(init) => {
+                    let mut scope = StaticScope::new(self);
+                    // Members may refer to earlier members of the same enum.
+                    for (k, v) in members.iter() {
+                        scope.bindings.insert(k.clone(), v.clone());
+                    }
+                    scope.eval(init)?
+                }
+                None => StaticValue::Num(next_num?),
+            };
+
+            next_num = match value {
+                StaticValue::Num(n) => Some(n + 1.0),
+                _ => None,
+            };
+            members.push((name, value));
+        }
+
+        Some(StaticValue::Object(members))
+    }
+}
+
+/*
+    What identifiers evaluate to: values bound directly (parameters), then the module's enums and
+    constants, which are evaluated on demand.
+*/
+pub(crate) struct StaticScope<'a> {
+    pub bindings: HashMap<String, StaticValue>,
+    statics: &'a ModuleStatics,
+    depth: usize,
+}
+
+impl<'a> StaticScope<'a> {
+    pub fn new(statics: &'a ModuleStatics) -> Self {
+        StaticScope {
+            bindings: HashMap::new(),
+            statics,
+            depth: 0,
+        }
+    }
+
+    pub fn eval(&mut self, e: &Expr) -> Option<StaticValue> {
+        match e {
+            Expr::Lit(lit) =>
+                match lit {
+                    Lit::Str(s) => Some(StaticValue::Str(s.value.to_string_lossy().into_owned())),
+                    Lit::Num(n) => Some(StaticValue::Num(n.value)),
+                    Lit::Bool(b) => Some(StaticValue::Bool(b.value)),
+                    Lit::Null(_) => Some(StaticValue::Null),
+                    _ => None,
+                }
+            Expr::Ident(i) => self.eval_ident(&i.sym),
+            Expr::Tpl(tpl) => {
+                let mut out = String::new();
+                for (idx, quasi) in tpl.quasis.iter().enumerate() {
+                    match &quasi.cooked {
+                        Some(cooked) => out.push_str(&cooked.to_string_lossy()),
+                        None => out.push_str(&quasi.raw),
+                    }
+                    if let Some(expr) = tpl.exprs.get(idx) {
+                        out.push_str(&self.eval(expr)?.to_js_string());
+                    }
+                }
+                Some(StaticValue::Str(out))
+            }
+            Expr::Array(arr) => {
+                let mut items = Vec::new();
+                for elem in arr.elems.iter() {
+                    let elem = elem.as_ref()?;
+                    match (elem.spread, self.eval(&elem.expr)?) {
+                        (None, v) => items.push(v),
+                        (Some(_), StaticValue::Array(spread)) => items.extend(spread),
+                        _ => {
+                            return None;
+                        }
+                    }
+                }
+                Some(StaticValue::Array(items))
+            }
+            Expr::Object(obj) => {
+                let mut props: Vec<(String, StaticValue)> = Vec::new();
+                for prop in obj.props.iter() {
+                    match prop {
+                        PropOrSpread::Spread(spread) =>
+                            match self.eval(&spread.expr)? {
+                                StaticValue::Object(spread_props) => props.extend(spread_props),
+                                _ => {
+                                    return None;
+                                }
+                            }
+                        PropOrSpread::Prop(prop) =>
+                            match &**prop {
+                                Prop::Shorthand(i) => {
+                                    props.push((String::from(&*i.sym), self.eval_ident(&i.sym)?));
+                                }
+                                Prop::KeyValue(kv) => {
+                                    let key = self.eval_prop_name(&kv.key)?;
+                                    props.push((key, self.eval(&kv.value)?));
+                                }
+                                _ => {
+                                    return None;
+                                }
+                            }
+                    }
+                }
+                Some(StaticValue::Object(props))
+            }
+            Expr::Member(MemberExpr { obj, prop, .. }) => {
+                let obj = self.eval(obj)?;
+                let key = match prop {
+                    MemberProp::Ident(i) => String::from(&*i.sym),
+                    MemberProp::Computed(c) => self.eval(&c.expr)?.to_js_string(),
+                    _ => {
+                        return None;
+                    }
+                };
+                obj.get(&key)
+            }
+            Expr::Unary(UnaryExpr { op, arg, .. }) => {
+                let arg = self.eval(arg)?;
+                match (op, arg) {
+                    (UnaryOp::Minus, StaticValue::Num(n)) => Some(StaticValue::Num(-n)),
+                    (UnaryOp::Plus, StaticValue::Num(n)) => Some(StaticValue::Num(n)),
+                    (UnaryOp::Bang, StaticValue::Bool(b)) => Some(StaticValue::Bool(!b)),
+                    _ => None,
+                }
+            }
+            Expr::Paren(ParenExpr { expr, .. }) => self.eval(expr),
+            Expr::TsAs(TsAsExpr { expr, .. }) => self.eval(expr),
+            Expr::TsConstAssertion(TsConstAssertion { expr, .. }) => self.eval(expr),
+            Expr::TsSatisfies(TsSatisfiesExpr { expr, .. }) => self.eval(expr),
+            Expr::TsNonNull(TsNonNullExpr { expr, .. }) => self.eval(expr),
+            _ => None,
+        }
+    }
+
+    pub fn eval_prop_name(&mut self, name: &PropName) -> Option<String> {
+        match name {
+            PropName::Ident(i) => Some(String::from(&*i.sym)),
+            PropName::Str(s) => Some(s.value.to_string_lossy().into_owned()),
+            PropName::Num(n) => Some(format_num(n.value)),
+            PropName::Computed(c) => Some(self.eval(&c.expr)?.to_js_string()),
+            _ => None,
+        }
+    }
+
+    fn eval_ident(&mut self, name: &str) -> Option<StaticValue> {
+        if let Some(v) = self.bindings.get(name) {
+            return Some(v.clone());
+        }
+        if name == "undefined" {
+            return Some(StaticValue::Undefined);
+        }
+        if let Some(v) = self.statics.enums.get(name) {
+            return Some(v.clone());
+        }
+
+        // Constants referencing each other in a loop never evaluate.
+        if self.depth > 32 {
+            return None;
+        }
+        let init = self.statics.consts.get(name)?;
+        self.depth += 1;
+        let res = self.eval(init);
+        self.depth -= 1;
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use swc_common::{ sync::Lrc, FileName, SourceMap };
+    use swc_ecma_parser::{ parse_file_as_module, Syntax };
+
+    fn parse(src: &str) -> Program {
+        let cm = Lrc::<SourceMap>::default();
+        let fm = cm.new_source_file(Lrc::new(FileName::Custom(String::from("test.ts"))), String::from(src));
+        let module = parse_file_as_module(
+            &fm,
+            Syntax::Typescript(Default::default()),
+            EsVersion::Es2020,
+            None,
+            &mut Vec::new()
+        ).expect("test source should parse");
+        Program::Module(module)
+    }
+
+    // Evaluates the module's `result` const.
+    fn eval_result(src: &str) -> Option<StaticValue> {
+        let statics = ModuleStatics::collect(&parse(src));
+        StaticScope::new(&statics).eval_ident("result")
+    }
+
+    fn text(s: &str) -> StaticValue {
+        StaticValue::Str(String::from(s))
+    }
+
+    #[test]
+    fn evaluates_literals_and_templates() {
+        assert_eq!(eval_result(r#"const result = "ios";"#), Some(text("ios")));
+        assert_eq!(eval_result("const result = -2;"), Some(StaticValue::Num(-2.0)));
+        assert_eq!(eval_result("const result = !true;"), Some(StaticValue::Bool(false)));
+        assert_eq!(eval_result("const result = undefined;"), Some(StaticValue::Undefined));
+        assert_eq!(eval_result(r#"const a = "x"; const result = `${a}-${2}`;"#), Some(text("x-2")));
+    }
+
+    #[test]
+    fn follows_consts_and_exports() {
+        assert_eq!(eval_result(r#"export const K = "k"; const result = K;"#), Some(text("k")));
+        assert_eq!(
+            eval_result(r#"const Platform = { Web: "web" } as const; const result = Platform.Web;"#),
+            Some(text("web"))
+        );
+        assert_eq!(
+            eval_result(r#"const keys = ["a", "b"]; const result = keys[1];"#),
+            Some(text("b"))
+        );
+    }
+
+    #[test]
+    fn numbers_enum_members_from_the_previous_one() {
+        let src = "enum E { A, B = 5, C } const a = E.A; const result = E.C;";
+        assert_eq!(eval_result(src), Some(StaticValue::Num(6.0)));
+        assert_eq!(
+            StaticScope::new(&ModuleStatics::collect(&parse(src))).eval_ident("a"),
+            Some(StaticValue::Num(0.0))
+        );
+    }
+
+    #[test]
+    fn enum_members_may_refer_to_earlier_members() {
+        assert_eq!(
+            eval_result(r#"enum P { IOS = "ios", Default = IOS } const result = P.Default;"#),
+            Some(text("ios"))
+        );
+    }
+
+    #[test]
+    fn spreads_arrays_and_objects() {
+        assert_eq!(
+            eval_result("const a = [1, 2]; const result = [...a, 3].length;"),
+            Some(StaticValue::Num(3.0))
+        );
+        assert_eq!(
+            eval_result(r#"const base = { a: "1", b: "2" }; const result = { ...base, b: "3" }.b;"#),
+            Some(text("3"))
+        );
+    }
+
+    #[test]
+    fn gives_up_on_anything_dynamic() {
+        assert_eq!(eval_result("const result = foo();"), None);
+        assert_eq!(eval_result("let x = 1; const result = x;"), None);
+        assert_eq!(eval_result("const result = missing;"), None);
+        // Consts referring to each other never settle.
+        assert_eq!(eval_result("const a = b; const b = a; const result = a;"), None);
+    }
+
+    #[test]
+    fn bindings_shadow_module_consts() {
+        let statics = ModuleStatics::collect(&parse(r#"const key = "module";"#));
+        let mut scope = StaticScope::new(&statics);
+        scope.bindings.insert(String::from("key"), text("param"));
+        assert_eq!(scope.eval_ident("key"), Some(text("param")));
+    }
+
+    #[test]
+    fn prints_values_as_js() {
+        assert_eq!(StaticValue::Num(5.0).to_js(), "5");
+        assert_eq!(StaticValue::Num(-0.0).to_js(), "0");
+        assert_eq!(StaticValue::Num(0.5).to_js(), "0.5");
+        assert_eq!(StaticValue::Num(9.3e18).to_js(), "9300000000000000000");
+        assert_eq!(StaticValue::Num(1e21).to_js(), "1e+21");
+        assert_eq!(StaticValue::Num(1.5e-7).to_js(), "1.5e-7");
+        assert_eq!(StaticValue::Num(f64::NAN).to_js(), "NaN");
+        assert_eq!(text("a\"b").to_js(), r#""a\"b""#);
+        assert_eq!(
+            StaticValue::Object(Vec::from([(String::from("a"), StaticValue::Array(Vec::from([StaticValue::Null])))])).to_js(),
+            r#"({ "a": [null] })"#
+        );
+    }
+
+    #[test]
+    fn converts_to_strings_like_js() {
+        let items = StaticValue::Array(Vec::from([StaticValue::Num(1.0), StaticValue::Null, text("a")]));
+        assert_eq!(items.to_js_string(), "1,,a");
+        assert_eq!(StaticValue::Object(Vec::new()).to_js_string(), "[object Object]");
+    }
+}
@@ -4,53 +4,155 @@ use core::panic;
 use std::{ collections::{ HashMap, HashSet }, str };
 use swc::PrintArgs;
 use swc_atoms::Atom;
-use swc_common::{ errors::{ ColorConfig, Handler }, sync::Lrc, FileName, SourceMap, DUMMY_SP };
+use swc_common::{
+    sync::Lrc,
+    FileName,
+    Globals,
+    Mark,
+    SourceFile,
+    SourceMap,
+    Span,
+    Spanned,
+    DUMMY_SP,
+    GLOBALS,
+};
 use swc_ecma_ast::*;
-use swc_ecma_parser::{ Syntax, TsConfig };
+use swc_ecma_parser::{ error::Error as ParseError, parse_file_as_module, Syntax, TsSyntax };
+use swc_ecma_transforms_base::resolver;
 use swc_ecma_visit::{ VisitMut, VisitMutWith, VisitWith };
 
-use crate::scan_first::ScanFirst;
-use crate::helpers::{ WalnutFinder, ObjectLitFinder };
-use crate::finalize::WalnutFinalize;
-use crate::resolver::try_resolve_resolver_label;
+use crate::scan_first::{ might_contain_walnut, ScanFirst };
+use crate::helpers::{ jsx_element_name, BindingRefs, WalnutFinder, ObjectLitFinder };
+use crate::finalize::{ UnusedWalnutRemover, WalnutFinalize };
+use crate::resolver::{ try_resolve_resolver_label, scan_module_labels, ResolvedLabel, WalnutReExports };
+use crate::diagnostics::{ Diagnostic, WalnutDiagnostic };
+use crate::builtins::resolve_builtin;
+use crate::condition::Condition;
+use crate::static_eval::{ ModuleStatics, StaticScope, StaticValue };
+use crate::options::WalnutOptions;
 
 pub(crate) struct WalnutSymbols;
 impl WalnutSymbols {
     pub const VAL: &'static str = "$Val";
     pub const PVAL: &'static str = "$PVal";
     pub const RESOLVE: &'static str = "$Resolve";
+    pub const WALNUT: &'static str = "$Walnut";
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Intrinsic {
+    Val,
+    PVal,
+    Resolve,
+    Walnut,
+    // The whole module, `import * as W from "walnut-ts"` or `const W = require("walnut-ts")`.
+    Namespace,
+}
+
+impl Intrinsic {
+    // The intrinsic exported by walnut under `name`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            WalnutSymbols::VAL => Some(Intrinsic::Val),
+            WalnutSymbols::PVAL => Some(Intrinsic::PVal),
+            WalnutSymbols::RESOLVE => Some(Intrinsic::Resolve),
+            WalnutSymbols::WALNUT => Some(Intrinsic::Walnut),
+            _ => None,
+        }
+    }
+
+    // What importing walnut's export `name` binds, `*` being the module namespace.
+    pub fn from_export(name: &str) -> Option<Self> {
+        match name {
+            "*" | "default" | "Walnut" => Some(Intrinsic::Namespace),
+            _ => Intrinsic::from_name(name),
+        }
+    }
+}
+
+/*
+    The local bindings walnut intrinsics were imported as. Tracking the binding (name and syntax
+    context) instead of the name makes aliases work and leaves shadowing locals alone.
+*/
+pub(crate) type WalnutBindings = HashMap<Id, Intrinsic>;
+
+// The intrinsic an expression names, `$Val` or `W.$Val` through a namespace.
+pub(crate) fn intrinsic_of(bindings: &WalnutBindings, e: &Expr) -> Option<Intrinsic> {
+    match e {
+        Expr::Ident(i) => bindings.get(&i.to_id()).copied(),
+        Expr::Member(MemberExpr { obj, prop: MemberProp::Ident(prop), .. }) => {
+            let Expr::Ident(ns) = &**obj else {
+                return None;
+            };
+            if bindings.get(&ns.to_id()) != Some(&Intrinsic::Namespace) {
+                return None;
+            }
+            Intrinsic::from_name(&prop.sym)
+        }
+        _ => None,
+    }
+}
+
+/*
+    The keys a walnut key activates, e.g. "ios, tablet". The first one is the primary key, which
+    is what resolvers are evaluated for.
+*/
+pub(crate) fn active_keys(walnut_key: &str) -> Vec<String> {
+    walnut_key
+        .split(',')
+        .map(|k| String::from(k.trim()))
+        .filter(|k| !k.is_empty())
+        .collect()
+}
+
+fn syntax_error(e: ParseError) -> Diagnostic {
+    Diagnostic::error(format!("Syntax error: {}", e.kind().msg()), e.span())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum WalnutJsx {
+    Gate,
+    Else,
+    Switch,
 }
 
 /*
     The main Walnut Transform struct.
 */
-struct WalnutTransform {
-    walnut_key: String,
-    resolver_ids: Vec<String>,
-    is_in_jsx: bool,
+pub(crate) struct WalnutTransform {
+    pub bindings: WalnutBindings,
+    // The walnut key may name several active keys, separated by commas.
+    active_keys: Vec<String>,
+    // The resolver binding of every $Resolve call, with the call site span.
+    resolver_ids: Vec<(Id, Span)>,
+    // Constants and enums of the module, keys may refer to them.
+    statics: ModuleStatics,
+    // Component names configured to act as $Walnut.
+    jsx_names: Vec<String>,
+    pub diagnostics: Vec<Diagnostic>,
 }
 
 impl WalnutTransform {
-    pub fn new(walnut_key: String) -> Self {
+    pub fn new(walnut_key: String, bindings: WalnutBindings, statics: ModuleStatics, jsx_names: Vec<String>) -> Self {
         WalnutTransform {
-            walnut_key: walnut_key,
+            bindings,
+            active_keys: active_keys(&walnut_key),
             resolver_ids: Vec::new(),
-            is_in_jsx: false,
+            statics,
+            jsx_names,
+            diagnostics: Vec::new(),
         }
     }
 
     fn transform_tool(&mut self, e: &mut CallExpr) -> Option<Expr> {
-        match e.callee.clone() {
-            Callee::Expr(callee) =>
-                match *callee {
-                    Expr::Ident(i) =>
-                        match &*i.sym {
-                            WalnutSymbols::VAL | WalnutSymbols::PVAL => self.transform_val(e),
-                            WalnutSymbols::RESOLVE => self.setup_resolve(e),
-                            _ => None,
-                        }
-                    _ => None,
-                }
+        let intrinsic = match &e.callee {
+            Callee::Expr(callee) => intrinsic_of(&self.bindings, callee),
+            _ => None,
+        };
+
+        match intrinsic {
+            Some(Intrinsic::Val | Intrinsic::PVal) => self.transform_val(e),
+            Some(Intrinsic::Resolve) => self.setup_resolve(e),
             _ => None,
         }
     }
@@ -67,266 +169,721 @@ impl WalnutTransform {
             }
         };
 
-        let matched_val = self.extract_val(&val_obj);
-
-        matched_val
+        self.extract_val(&val_obj)
     }
 
+    // The value for the highest priority active key the object lists, or its first value if it
+    // lists none of them.
     fn extract_val(&mut self, val_obj: &ObjectLit) -> Option<Expr> {
-        let mut matched_val: Option<Expr> = None;
-
+        let mut entries: Vec<(Option<String>, Expr)> = Vec::new();
         for prop in val_obj.props.iter() {
-            let p = match prop {
-                PropOrSpread::Prop(prop) => *prop.clone(),
-                _ => { panic!("Spread operators disallowed.") }
-            };
-
-            let (prop_name, val) = match p.key_value() {
-                Some(v) => (v.key, *v.value.clone()),
-                _ => panic!("No value found for prop"),
-            };
-
-            let key = match prop_name {
-                PropName::Ident(k) => String::from(&*k.sym),
-                PropName::Str(k) => k.value.to_string(),
-                PropName::Num(k) => k.to_string(),
-                PropName::BigInt(k) => k.value.to_string(),
-                _ => panic!("Keys must be of type string or number"),
-            };
+            // An object we can't read entirely is left as is, after reporting why.
+            let (prop_name, val) = self.val_entry(prop, val_obj.span)?;
+            entries.push((self.val_key(&prop_name, val_obj.span), val));
+        }
 
-            if key == self.walnut_key {
-                matched_val = Some(val);
-                break;
+        for active in self.active_keys.iter() {
+            if let Some((_, val)) = entries.iter().find(|(key, _)| key.as_ref() == Some(active)) {
+                return Some(val.clone());
             }
         }
 
-        if matched_val == None {
-            let first_prop = match val_obj.props.get(0) {
-                Some(v) => v,
-                None => {
-                    return None;
-                }
-            };
+        entries.into_iter().next().map(|(_, val)| val)
+    }
 
-            let p = match first_prop {
-                PropOrSpread::Prop(v) => *v.clone(),
-                _ => panic!("Spread operators disallowed."),
-            };
+    // `key: value`, or the `key` shorthand. Spreads and methods can't be picked from at build time.
+    fn val_entry(&mut self, prop: &PropOrSpread, span: Span) -> Option<(PropName, Expr)> {
+        let PropOrSpread::Prop(prop) = prop else {
+            self.diagnostics.push(
+                Diagnostic::error(String::from("Spreads aren't supported in $Val objects, list every key"), span)
+            );
+            return None;
+        };
 
-            let val = match p.key_value() {
-                Some(v) => *v.value.clone(),
-                _ => panic!("No value found for prop"),
-            };
+        match &**prop {
+            Prop::KeyValue(kv) => Some((kv.key.clone(), *kv.value.clone())),
+            Prop::Shorthand(i) => Some((PropName::Ident(IdentName::new(i.sym.clone(), i.span)), Expr::Ident(i.clone()))),
+            _ => {
+                self.diagnostics.push(
+                    Diagnostic::error(String::from("$Val entries must be written as `key: value` or `key`"), span)
+                );
+                None
+            }
+        }
+    }
+
+    // `[Platform.IOS]: ...` and `[IOS]: ...` are evaluated like JSX keys.
+    fn val_key(&mut self, prop_name: &PropName, span: Span) -> Option<String> {
+        if let PropName::BigInt(k) = prop_name {
+            return Some(k.value.to_string());
+        }
 
-            matched_val = Some(val);
+        let key = StaticScope::new(&self.statics).eval_prop_name(prop_name);
+        if key.is_none() {
+            self.diagnostics.push(
+                Diagnostic::error(
+                    String::from("Keys of $Val must be known at build time, such as a const or an enum member"),
+                    span
+                )
+            );
         }
+        key
+    }
 
-        matched_val
+    // Every key a $Val object lists, in order.
+    pub(crate) fn val_keys(&mut self, val_obj: &ObjectLit) -> Vec<String> {
+        val_obj.props
+            .iter()
+            .filter_map(|prop| {
+                let (prop_name, _) = self.val_entry(prop, val_obj.span)?;
+                self.val_key(&prop_name, val_obj.span)
+            })
+            .collect()
     }
 
     fn setup_resolve(&mut self, e: &mut CallExpr) -> Option<Expr> {
-        let arg = match e.args.get(0) {
+        let arg = match e.args.first() {
             Some(v) => v.clone(),
             None => {
                 return None;
             }
         };
 
-        let resolver_id = match *arg.expr {
-            Expr::Ident(i) => {
-                //(i.to_id(), String::from(&*i.sym))
-                String::from(&*i.sym)
-            }
+        let resolver_binding = match *arg.expr {
+            Expr::Ident(i) => i.to_id(),
             _ => {
                 return None;
             }
         };
 
-        let mark_string = format!("/* __wres_{resolver_id} */");
+        let mark_string = format!("/* __wres_{} */", resolver_binding.0);
 
         let mark_val = Atom::new(mark_string);
 
         let marker = Expr::Lit(
             Lit::Str(Str {
                 span: DUMMY_SP,
-                value: mark_val.clone(),
+                value: mark_val.clone().into(),
                 raw: Some(mark_val.clone()),
             })
         );
 
-        self.resolver_ids.push(resolver_id);
+        self.resolver_ids.push((resolver_binding, e.span));
 
         Some(marker)
     }
 
-    fn is_valid_jsx_identifier(&mut self, name: &JSXElementName) -> bool {
+    fn is_walnut_binding(&self, i: &Ident) -> bool {
+        self.bindings.get(&i.to_id()) == Some(&Intrinsic::Walnut)
+    }
+
+    // `W.$Walnut` through a namespace.
+    fn is_namespaced_walnut(&self, m: &JSXMemberExpr) -> bool {
+        match &m.obj {
+            JSXObject::Ident(ns) =>
+                self.bindings.get(&ns.to_id()) == Some(&Intrinsic::Namespace) &&
+                    &*m.prop.sym == WalnutSymbols::WALNUT,
+            _ => false,
+        }
+    }
+
+    fn is_walnut_object(&self, obj: &JSXObject) -> bool {
+        match obj {
+            JSXObject::Ident(i) => self.is_walnut_binding(i),
+            JSXObject::JSXMemberExpr(m) => self.is_namespaced_walnut(m),
+        }
+    }
+
+    // `<$Walnut>`, `<$Walnut.Else>` or `<$Walnut.Switch>`, or the same for a configured component name.
+    pub(crate) fn walnut_jsx_kind(&self, name: &JSXElementName) -> Option<WalnutJsx> {
         match name {
-            JSXElementName::Ident(s) =>
-                match &*s.sym {
-                    "$Walnut" => true,
-                    _ => false,
+            JSXElementName::Ident(s) if self.is_walnut_binding(s) => Some(WalnutJsx::Gate),
+            JSXElementName::JSXMemberExpr(m) if self.is_namespaced_walnut(m) => Some(WalnutJsx::Gate),
+            JSXElementName::JSXMemberExpr(JSXMemberExpr { obj, prop, .. }) if self.is_walnut_object(obj) =>
+                match &*prop.sym {
+                    "Else" => Some(WalnutJsx::Else),
+                    "Switch" => Some(WalnutJsx::Switch),
+                    _ => None,
                 }
-            _ => false,
+            _ => self.configured_jsx_kind(name),
         }
     }
 
-    fn handle_element(&mut self, element: &JSXElement) -> bool {
-        //if !self.is_valid_jsx_identifier(&element.opening.name){ return }
-
-        let Some(key_vec) = element.opening.attrs.iter().find_map(|jsx_attr_or_spread| {
-            match jsx_attr_or_spread {
-                JSXAttrOrSpread::JSXAttr(
-                    JSXAttr { name: JSXAttrName::Ident(Ident { sym, .. }), value, .. },
-                ) if sym == "key" => {
-                    match value {
-                        Some(val) => {
-                            match val {
-                                JSXAttrValue::JSXExprContainer(jsx_expr_cont) => {
-                                    match jsx_expr_cont.expr.clone() {
-                                        JSXExpr::Expr(e) => {
-                                            match *e.clone() {
-                                                Expr::Array(arr) => {
-                                                    let mut ret_vec = Vec::<String>::new();
-                                                    for itm in arr.elems {
-                                                        match itm {
-                                                            Some(expr_or_sprd) => {
-                                                                match *expr_or_sprd.expr.clone() {
-                                                                    Expr::Lit(Lit::Str(s)) =>
-                                                                        ret_vec.push(
-                                                                            String::from(&*s.value)
-                                                                        ),
-                                                                    _ => {
-                                                                        return None;
-                                                                    }
-                                                                }
-                                                            }
-                                                            None => {
-                                                                return None;
-                                                            }
-                                                        }
-                                                    }
-                                                    Some(ret_vec)
-                                                }
-                                                Expr::Lit(Lit::Str(s)) => {
-                                                    Some(
-                                                        Vec::<String>::from([
-                                                            String::from(&*s.value),
-                                                        ])
-                                                    )
-                                                }
-                                                _ => None,
-                                            }
-                                        }
-                                        _ => None,
-                                    }
+    // Names from the `jsxNames` option are matched as written, `<Walnut.Only>` is a gate if "Walnut.Only" is listed.
+    fn configured_jsx_kind(&self, name: &JSXElementName) -> Option<WalnutJsx> {
+        if self.jsx_names.is_empty() {
+            return None;
+        }
+
+        let name = jsx_element_name(name);
+        if self.jsx_names.contains(&name) {
+            return Some(WalnutJsx::Gate);
+        }
+
+        let (base, prop) = name.rsplit_once('.')?;
+        if !self.jsx_names.iter().any(|n| n == base) {
+            return None;
+        }
+        match prop {
+            "Else" => Some(WalnutJsx::Else),
+            "Switch" => Some(WalnutJsx::Switch),
+            _ => None,
+        }
+    }
+
+    // A `<$Walnut fallback>` is just another way of writing `<$Walnut.Else>`.
+    pub(crate) fn is_fallback(&mut self, element: &JSXElement) -> bool {
+        let has_fallback = element.opening.attrs.iter().any(|attr| {
+            matches!(
+                attr,
+                JSXAttrOrSpread::JSXAttr(JSXAttr { name: JSXAttrName::Ident(i), .. }) if &*i.sym == "fallback"
+            )
+        });
+
+        if has_fallback && element.opening.attrs.len() > 1 {
+            self.diagnostics.push(
+                Diagnostic::error(
+                    String::from("`fallback` can't be combined with other attributes on <$Walnut>"),
+                    element.opening.span
+                )
+            );
+        }
+
+        has_fallback
+    }
+
+    /*
+        Replaces <$Walnut> children with the children of the ones that match.
+        A run of <$Walnut> siblings (whitespace in between is fine) forms a group, and a
+        <$Walnut.Else> or <$Walnut fallback> right after it is kept only when nothing in the group matched.
+    */
+    fn filter_children(&mut self, children: &[JSXElementChild]) -> Vec<JSXElementChild> {
+        let mut new_children: Vec<JSXElementChild> = Vec::new();
+        // Whether anything in the current group of siblings matched, None outside a group.
+        let mut group_matched: Option<bool> = None;
+
+        for child in children.iter() {
+            match child {
+                JSXElementChild::JSXElement(el) => {
+                    let Some(kind) = self.walnut_jsx_kind(&el.opening.name) else {
+                        new_children.push(child.clone());
+                        group_matched = None;
+                        continue;
+                    };
+
+                    match kind {
+                        WalnutJsx::Gate if !self.is_fallback(el) => {
+                            // An element we couldn't decide on is left as is, an error has been reported for it.
+                            let matched = match self.handle_element(el) {
+                                Some(true) => {
+                                    new_children.extend(el.children.iter().cloned());
+                                    true
                                 }
-                                JSXAttrValue::Lit(Lit::Str(s)) => {
-                                    Some(Vec::<String>::from([String::from(&*s.value)]))
+                                Some(false) => false,
+                                None => {
+                                    new_children.push(child.clone());
+                                    false
                                 }
-                                _ => None,
+                            };
+                            group_matched = Some(group_matched.unwrap_or(false) || matched);
+                        }
+                        WalnutJsx::Gate | WalnutJsx::Else => {
+                            if group_matched != Some(true) {
+                                new_children.extend(el.children.iter().cloned());
                             }
+                            group_matched = None;
                         }
-                        _ => None,
+                        WalnutJsx::Switch => {
+                            new_children.extend(self.handle_switch(el));
+                            group_matched = None;
+                        }
+                    }
+                }
+                JSXElementChild::JSXText(text) if text.value.to_string_lossy().trim().is_empty() => {
+                    new_children.push(child.clone());
+                }
+                _ => {
+                    new_children.push(child.clone());
+                    group_matched = None;
+                }
+            }
+        }
+
+        new_children
+    }
+
+    /*
+        <$Walnut.Switch> keeps the children of its first matching case, or of its
+        <$Walnut.Else> when no case matches.
+    */
+    fn handle_switch(&mut self, switch: &JSXElement) -> Vec<JSXElementChild> {
+        let mut chosen: Option<&JSXElement> = None;
+        let mut fallback: Option<&JSXElement> = None;
+
+        for child in switch.children.iter() {
+            let el = match child {
+                JSXElementChild::JSXText(text) if text.value.to_string_lossy().trim().is_empty() => {
+                    continue;
+                }
+                JSXElementChild::JSXElement(el) => el,
+                _ => {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            String::from("<$Walnut.Switch> can only contain <$Walnut> cases and <$Walnut.Else>"),
+                            switch.opening.span
+                        )
+                    );
+                    continue;
+                }
+            };
+
+            match self.walnut_jsx_kind(&el.opening.name) {
+                Some(WalnutJsx::Gate) if !self.is_fallback(el) => {
+                    if self.handle_element(el) != Some(true) {
+                        continue;
+                    }
+                    if chosen.is_some() {
+                        self.diagnostics.push(
+                            Diagnostic::warning(
+                                String::from("More than one case matches in <$Walnut.Switch>, keeping the first"),
+                                el.opening.span
+                            )
+                        );
+                        continue;
                     }
+                    chosen = Some(el);
+                }
+                Some(WalnutJsx::Gate) | Some(WalnutJsx::Else) => {
+                    fallback = Some(el);
+                }
+                _ => {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            String::from("<$Walnut.Switch> can only contain <$Walnut> cases and <$Walnut.Else>"),
+                            el.opening.span
+                        )
+                    );
                 }
-                _ => None,
             }
-        }) else {
-            return false;
+        }
+
+        match chosen.or(fallback) {
+            Some(el) => el.children.clone(),
+            None => Vec::new(),
+        }
+    }
+
+    /*
+        Decides whether a <$Walnut> element is kept. Every condition attribute has to hold:
+            key / any   one of the listed keys is active
+            all         every listed key is active
+            not         none of the listed keys are active
+            when        a condition such as "ios && !tablet", or a list of keys like `any`
+        Returns None when that can't be decided, after reporting why.
+    */
+    fn handle_element(&mut self, element: &JSXElement) -> Option<bool> {
+        let mut matches_walnut_key = true;
+        let mut has_condition = false;
+
+        for jsx_attr_or_spread in element.opening.attrs.iter() {
+            let JSXAttrOrSpread::JSXAttr(attr) = jsx_attr_or_spread else {
+                self.diagnostics.push(
+                    Diagnostic::error(String::from("Spread attributes aren't supported on <$Walnut>"), element.opening.span)
+                );
+                return None;
+            };
+
+            let name = match &attr.name {
+                JSXAttrName::Ident(i) => String::from(&*i.sym),
+                JSXAttrName::JSXNamespacedName(n) => format!("{}:{}", n.ns.sym, n.name.sym),
+            };
+
+            let holds = match name.as_str() {
+                "key" | "any" => {
+                    let keys = self.attr_keys(&name, attr)?;
+                    keys.iter().any(|k| self.active_keys.contains(k))
+                }
+                "all" => {
+                    let keys = self.attr_keys(&name, attr)?;
+                    keys.iter().all(|k| self.active_keys.contains(k))
+                }
+                "not" => {
+                    let keys = self.attr_keys(&name, attr)?;
+                    !keys.iter().any(|k| self.active_keys.contains(k))
+                }
+                "when" => {
+                    let condition = self.attr_condition(attr)?;
+                    condition.eval(&self.active_keys)
+                }
+                _ => {
+                    self.diagnostics.push(
+                        Diagnostic::error(
+                            format!("Unknown attribute `{name}` on <$Walnut>, expected one of key, when, not, any or all"),
+                            attr.span
+                        )
+                    );
+                    return None;
+                }
+            };
+
+            has_condition = true;
+            matches_walnut_key = matches_walnut_key && holds;
+        }
+
+        if !has_condition {
+            self.diagnostics.push(
+                Diagnostic::error(String::from("<$Walnut> needs a key, when, not, any or all attribute"), element.opening.span)
+            );
+            return None;
+        }
+
+        Some(matches_walnut_key)
+    }
+
+    // A `when` attribute, either a condition string or a list of keys.
+    pub(crate) fn attr_condition(&mut self, attr: &JSXAttr) -> Option<Condition> {
+        if let Some(StaticValue::Str(s)) = self.attr_value(attr) {
+            return match Condition::parse(&s) {
+                Ok(cond) => Some(cond),
+                Err(e) => {
+                    self.diagnostics.push(
+                        Diagnostic::error(format!("Invalid `when` condition on <$Walnut>: {e}"), attr.span)
+                    );
+                    None
+                }
+            };
+        }
+
+        let keys = self.attr_keys("when", attr)?;
+        keys.into_iter()
+            .map(Condition::Key)
+            .reduce(|a, b| Condition::Or(Box::new(a), Box::new(b)))
+    }
+
+    /*
+        The keys listed by an attribute: `attr="a"`, `attr={"a"}` or `attr={["a", "b"]}`, where any of
+        them may also be a same-module const, an `as const` object member or a string enum member.
+    */
+    pub(crate) fn attr_keys(&mut self, name: &str, attr: &JSXAttr) -> Option<Vec<String>> {
+        let keys = match self.attr_value(attr) {
+            Some(StaticValue::Str(s)) => Some(Vec::from([s])),
+            Some(StaticValue::Array(items)) =>
+                items
+                    .into_iter()
+                    .map(|item| {
+                        match item {
+                            StaticValue::Str(s) => Some(s),
+                            _ => None,
+                        }
+                    })
+                    .collect(),
+            _ => None,
         };
 
-        //println!("{:?}", key_val_expr);
+        if keys.is_none() {
+            self.diagnostics.push(
+                Diagnostic::error(
+                    format!(
+                        "`{name}` on <$Walnut> must be a string or an array of strings known at build time, such as a literal, a const or an enum member"
+                    ),
+                    attr.span
+                )
+            );
+        }
+
+        keys
+    }
+
+    fn attr_value(&self, attr: &JSXAttr) -> Option<StaticValue> {
+        match &attr.value {
+            Some(JSXAttrValue::Str(s)) => Some(StaticValue::Str(s.value.to_string_lossy().into_owned())),
+            Some(JSXAttrValue::JSXExprContainer(JSXExprContainer { expr: JSXExpr::Expr(e), .. })) =>
+                StaticScope::new(&self.statics).eval(e),
+            _ => None,
+        }
+    }
+
+    /*
+        Applies variant props to an element:
+            <Button walnut:ios={{ size: "lg" }} walnut:web={{ size: "md" }} />
+            <Button $props={{ ios: { size: "lg" }, web: { size: "md" } }} />
+        The props of active keys become regular attributes and override explicit props of the same
+        name, wherever those are written. Props for inactive keys are dropped.
+    */
+    fn apply_variant_props(&mut self, opening: &mut JSXOpeningElement) {
+        let mut variant_props: Vec<PropOrSpread> = Vec::new();
+        let mut has_variant_props = false;
+
+        for attr in opening.attrs.iter() {
+            let JSXAttrOrSpread::JSXAttr(attr) = attr else {
+                continue;
+            };
+
+            match &attr.name {
+                JSXAttrName::JSXNamespacedName(n) if &*n.ns.sym == "walnut" => {
+                    has_variant_props = true;
+                    let Some(obj) = self.attr_object(attr) else {
+                        continue;
+                    };
+                    if self.active_keys.iter().any(|k| k.as_str() == &*n.name.sym) {
+                        variant_props.extend(obj.props.iter().cloned());
+                    }
+                }
+                JSXAttrName::Ident(i) if &*i.sym == "$props" => {
+                    has_variant_props = true;
+                    let Some(obj) = self.attr_object(attr) else {
+                        continue;
+                    };
+                    for prop in obj.props.iter() {
+                        let entry = match prop {
+                            PropOrSpread::Prop(p) =>
+                                match &**p {
+                                    Prop::KeyValue(KeyValueProp { key: PropName::Ident(k), value }) => {
+                                        Some((String::from(&*k.sym), value))
+                                    }
+                                    Prop::KeyValue(KeyValueProp { key: PropName::Str(k), value }) => {
+                                        Some((k.value.to_string_lossy().into_owned(), value))
+                                    }
+                                    _ => None,
+                                }
+                            _ => None,
+                        };
+                        let Some((key, value)) = entry else {
+                            self.diagnostics.push(
+                                Diagnostic::error(String::from("`$props` entries must be `key: { ...props }`"), attr.span)
+                            );
+                            continue;
+                        };
+                        if !self.active_keys.contains(&key) {
+                            continue;
+                        }
+                        match &**value {
+                            Expr::Object(obj) => variant_props.extend(obj.props.iter().cloned()),
+                            _ => {
+                                self.diagnostics.push(
+                                    Diagnostic::error(format!("`$props` entry `{key}` must be an object literal"), attr.span)
+                                );
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
 
-        let mut matches_walnut_key: bool = false;
+        if !has_variant_props {
+            return;
+        }
 
-        for key in key_vec {
-            if key == self.walnut_key {
-                matches_walnut_key = true;
+        let mut new_attrs: Vec<JSXAttrOrSpread> = Vec::new();
+        let mut overridden: Vec<String> = Vec::new();
+        for prop in variant_props.into_iter() {
+            match prop {
+                PropOrSpread::Spread(spread) => {
+                    new_attrs.push(JSXAttrOrSpread::SpreadElement(spread));
+                }
+                PropOrSpread::Prop(p) => {
+                    let (name, value) = match *p {
+                        Prop::Shorthand(i) => (String::from(&*i.sym), Expr::Ident(i)),
+                        Prop::KeyValue(KeyValueProp { key: PropName::Ident(k), value }) => {
+                            (String::from(&*k.sym), *value)
+                        }
+                        Prop::KeyValue(KeyValueProp { key: PropName::Str(k), value }) => {
+                            (k.value.to_string_lossy().into_owned(), *value)
+                        }
+                        _ => {
+                            self.diagnostics.push(
+                                Diagnostic::error(
+                                    String::from("Variant props must be plain `name: value` or shorthand properties"),
+                                    opening.span
+                                )
+                            );
+                            continue;
+                        }
+                    };
+
+                    let value = match value {
+                        Expr::Lit(Lit::Str(s)) => JSXAttrValue::Str(s),
+                        e =>
+                            JSXAttrValue::JSXExprContainer(JSXExprContainer {
+                                span: DUMMY_SP,
+                                expr: JSXExpr::Expr(Box::new(e)),
+                            }),
+                    };
+
+                    // A later variant prop overrides an earlier one of the same name.
+                    new_attrs.retain(|a| {
+                        !matches!(a, JSXAttrOrSpread::JSXAttr(JSXAttr { name: JSXAttrName::Ident(i), .. }) if *i.sym == name)
+                    });
+                    new_attrs.push(
+                        JSXAttrOrSpread::JSXAttr(JSXAttr {
+                            span: DUMMY_SP,
+                            name: JSXAttrName::Ident(IdentName::new(Atom::from(name.as_str()), DUMMY_SP)),
+                            value: Some(value),
+                        })
+                    );
+                    overridden.push(name);
+                }
             }
         }
 
-        //println!("{}", matches_walnut_key);
+        opening.attrs.retain(|attr| {
+            match attr {
+                JSXAttrOrSpread::JSXAttr(JSXAttr { name: JSXAttrName::JSXNamespacedName(n), .. }) => {
+                    &*n.ns.sym != "walnut"
+                }
+                JSXAttrOrSpread::JSXAttr(JSXAttr { name: JSXAttrName::Ident(i), .. }) => {
+                    &*i.sym != "$props" && !overridden.iter().any(|o| o == &*i.sym)
+                }
+                _ => true,
+            }
+        });
+        opening.attrs.extend(new_attrs);
+    }
+
+    fn attr_object<'a>(&mut self, attr: &'a JSXAttr) -> Option<&'a ObjectLit> {
+        if let Some(JSXAttrValue::JSXExprContainer(JSXExprContainer { expr: JSXExpr::Expr(e), .. })) = &attr.value {
+            if let Expr::Object(obj) = &**e {
+                return Some(obj);
+            }
+        }
 
-        matches_walnut_key
+        self.diagnostics.push(
+            Diagnostic::error(String::from("Variant props must be given as an object literal, e.g. {{ size: \"lg\" }}"), attr.span)
+        );
+        None
     }
 
-    fn handle_fragment(&mut self, frag: &mut JSXFragment) {
-        frag.visit_mut_children_with(self);
+    /*
+        What a <$Walnut> outside of a children list becomes: its kept children as a single
+        element, wrapped in a fragment when there's more than one, or null when nothing is kept.
+    */
+    fn replace_root_element(&mut self, el: &JSXElement, kind: WalnutJsx) -> Expr {
+        let kept = match kind {
+            WalnutJsx::Gate if !self.is_fallback(el) =>
+                match self.handle_element(el) {
+                    Some(true) => Some(el.children.clone()),
+                    Some(false) => None,
+                    // Left as is, an error has been reported for it.
+                    None => {
+                        return Expr::JSXElement(Box::new(el.clone()));
+                    }
+                }
+            // With no siblings to defer to a fallback always renders.
+            WalnutJsx::Gate | WalnutJsx::Else => Some(el.children.clone()),
+            WalnutJsx::Switch => Some(self.handle_switch(el)),
+        };
+
+        let children: Vec<JSXElementChild> = kept
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|c| {
+                !matches!(c, JSXElementChild::JSXText(t) if t.value.to_string_lossy().trim().is_empty() && t.value.to_string_lossy().contains('\n'))
+            })
+            .collect();
+
+        match children.as_slice() {
+            [] => Expr::Lit(Lit::Null(Null { span: DUMMY_SP })),
+            [JSXElementChild::JSXElement(child)] => Expr::JSXElement(child.clone()),
+            [JSXElementChild::JSXFragment(child)] => Expr::JSXFragment(child.clone()),
+            [JSXElementChild::JSXExprContainer(JSXExprContainer { expr: JSXExpr::Expr(e), .. })] => {
+                *e.clone()
+            }
+            _ =>
+                Expr::JSXFragment(JSXFragment {
+                    span: el.span,
+                    opening: JSXOpeningFragment { span: DUMMY_SP },
+                    children,
+                    closing: JSXClosingFragment { span: DUMMY_SP },
+                }),
+        }
     }
 }
 
 impl VisitMut for WalnutTransform {
     fn visit_mut_var_declarator(&mut self, n: &mut VarDeclarator) {
         let mut walnut_call_expr = {
-            let mut v = WalnutFinder::new();
+            let mut v = WalnutFinder::new(&self.bindings);
             n.visit_with(&mut v);
             match v.res {
                 Some(v) => v,
                 None => {
+                    n.visit_mut_children_with(self);
                     return;
                 }
             }
         };
 
+        let reported = self.diagnostics.len();
         let new_node = self.transform_tool(&mut walnut_call_expr);
 
-        // In case we get back None for whatever reason (we shouldn't, we should panic) we mark as invalid.
+        // A call we can't make sense of stays as written, its import is kept and reported along with it.
         match new_node {
             Some(expr) => {
                 n.init = Some(Box::new(expr));
             }
+            // Already explained.
+            None if self.diagnostics.len() > reported => {}
             None => {
-                n.name = Pat::Invalid(Invalid { span: DUMMY_SP });
+                self.diagnostics.push(
+                    Diagnostic::error(
+                        String::from(
+                            "walnut can't transform this call, $Val and $PVal take an object literal and $Resolve a resolver"
+                        ),
+                        walnut_call_expr.span
+                    )
+                );
             }
         }
     }
 
-    fn visit_mut_jsx_fragment(&mut self, n: &mut JSXFragment) {
-        if self.is_in_jsx {
-            return;
+    fn visit_mut_expr(&mut self, n: &mut Expr) {
+        n.visit_mut_children_with(self);
+
+        // `return <$Walnut ...>`, `cond && <$Walnut ...>`, `.map(x => <$Walnut ...>)` and so on.
+        if let Expr::JSXElement(el) = n {
+            if let Some(kind) = self.walnut_jsx_kind(&el.opening.name) {
+                *n = self.replace_root_element(&el.clone(), kind);
+            }
         }
-        self.is_in_jsx = true;
+    }
+
+    fn visit_mut_jsx_attr_value(&mut self, n: &mut JSXAttrValue) {
         n.visit_mut_children_with(self);
-        self.is_in_jsx = false;
+
+        if let JSXAttrValue::JSXElement(el) = n {
+            if let Some(kind) = self.walnut_jsx_kind(&el.opening.name) {
+                let span = el.span;
+                let expr = self.replace_root_element(&el.clone(), kind);
+                *n = JSXAttrValue::JSXExprContainer(JSXExprContainer {
+                    span,
+                    expr: JSXExpr::Expr(Box::new(expr)),
+                });
+            }
+        }
     }
 
-    fn visit_mut_jsx_element(&mut self, n: &mut JSXElement) {
-        let old_is_in_jsx = self.is_in_jsx;
-        self.is_in_jsx = true;
+    fn visit_mut_jsx_opening_element(&mut self, n: &mut JSXOpeningElement) {
+        n.visit_mut_children_with(self);
+
+        self.apply_variant_props(n);
+    }
 
+    fn visit_mut_jsx_fragment(&mut self, n: &mut JSXFragment) {
         n.visit_mut_children_with(self);
-        if n.closing == None {
-            self.is_in_jsx = old_is_in_jsx;
-            return;
-        }
 
-        let mut new_children: Vec<JSXElementChild> = Vec::new();
+        n.children = self.filter_children(&n.children);
+    }
 
-        for child in n.children.iter() {
-            match child {
-                JSXElementChild::JSXFragment(frag) => {
-                    self.handle_fragment(&mut frag.clone());
-                    new_children.push(child.clone());
-                }
-                JSXElementChild::JSXElement(el) => {
-                    if !self.is_valid_jsx_identifier(&el.opening.name) {
-                        new_children.push(child.clone());
-                        continue;
-                    }
-                    if self.handle_element(&el) {
-                        for el_ch in el.children.iter() {
-                            new_children.push(el_ch.clone());
-                        }
-                    }
-                }
-                _ => {
-                    new_children.push(child.clone());
-                }
-            }
+    fn visit_mut_jsx_element(&mut self, n: &mut JSXElement) {
+        n.visit_mut_children_with(self);
+
+        // A switch's cases are picked from by its parent, they mustn't be filtered like siblings.
+        if self.walnut_jsx_kind(&n.opening.name) == Some(WalnutJsx::Switch) {
+            return;
         }
 
-        n.children = new_children;
-        self.is_in_jsx = old_is_in_jsx;
+        n.children = self.filter_children(&n.children);
     }
 }
 
@@ -338,7 +895,9 @@ impl VisitMut for WalnutTransform {
 #[napi]
 pub struct WalnutHandler {
     compiler: swc::Compiler,
-    program: Program,
+    fm: Lrc<SourceFile>,
+    // Only parsed once run() has seen the file might use walnut.
+    program: Option<Program>,
     walnut_key: String,
     input_code: String,
     output_code: Option<String>,
@@ -346,38 +905,25 @@ pub struct WalnutHandler {
     resolver_labels: Vec<String>,
     label_map: HashMap<String, String>,
     entry_id: String,
+    options: WalnutOptions,
+    walnut_sources: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+    // Off for graph walks, which only need the transformed imports.
+    evaluate_resolvers: bool,
 }
 
 #[napi]
 impl WalnutHandler {
-    pub fn new(code: String, id: String, walnut_key: String) -> Self {
+    pub fn new(code: String, id: String, walnut_key: String, options: WalnutOptions) -> Self {
         let cm = Lrc::<SourceMap>::default();
-        let handler = Handler::with_tty_emitter(ColorConfig::Auto, true, false, Some(cm.clone()));
-
         let compiler = swc::Compiler::new(cm.clone());
 
-        let fm = cm.new_source_file(FileName::Custom(id.clone()), code.clone());
-
-        let program = compiler
-            .parse_js(
-                fm.clone(),
-                &handler,
-                EsVersion::Es2020,
-                Syntax::Typescript(TsConfig {
-                    tsx: true,
-                    decorators: false,
-                    dts: false,
-                    no_early_errors: true,
-                    disallow_ambiguous_jsx_like: true,
-                }),
-                swc::config::IsModule::Bool(true),
-                Default::default()
-            )
-            .expect("Failed to parse");
+        let fm = cm.new_source_file(Lrc::new(FileName::Custom(id.clone())), code.clone());
 
         WalnutHandler {
             compiler,
-            program,
+            fm,
+            program: None,
             walnut_key,
             input_code: code,
             output_code: None,
@@ -385,58 +931,271 @@ impl WalnutHandler {
             entry_id: id,
             resolver_labels: Vec::new(),
             label_map: HashMap::new(),
+            options,
+            walnut_sources: Vec::new(),
+            diagnostics: Vec::new(),
+            evaluate_resolvers: true,
+        }
+    }
+
+    // Syntax errors come back as diagnostics rather than a panic, the file is then left as is.
+    fn parse(&self) -> Result<Program, Vec<Diagnostic>> {
+        let mut recovered = Vec::new();
+        let parsed = parse_file_as_module(
+            &self.fm,
+            Syntax::Typescript(TsSyntax {
+                tsx: true,
+                decorators: false,
+                dts: false,
+                no_early_errors: true,
+                disallow_ambiguous_jsx_like: true,
+            }),
+            EsVersion::Es2020,
+            None,
+            &mut recovered
+        );
+
+        let mut errors: Vec<Diagnostic> = recovered.into_iter().map(syntax_error).collect();
+        match parsed {
+            Ok(module) if errors.is_empty() => Ok(Program::Module(module)),
+            Ok(_) => Err(errors),
+            Err(e) => {
+                errors.push(syntax_error(e));
+                Err(errors)
+            }
         }
     }
 
+    /*
+        Transforms a program parsed from this handler's code for a dependency graph walk. Only
+        what the program still imports matters there, so resolvers aren't looked up or evaluated.
+    */
+    pub(crate) fn transform_for_graph(mut self, mut program: Program) -> Program {
+        self.evaluate_resolvers = false;
+        GLOBALS.set(&Globals::new(), || self.run_passes(&mut program));
+        program
+    }
+
     #[napi]
     pub fn run(&mut self) {
-        let mut scan_first = ScanFirst::new();
-        self.program.visit_mut_with(&mut scan_first);
-
-        if !scan_first.should_run {
+        // Most files have nothing to do with walnut, those aren't even parsed.
+        if !might_contain_walnut(&self.input_code, &self.options) {
             self.output_code = Some(self.input_code.clone());
             return;
         }
 
-        // Transform pass
-        let mut w_trans = WalnutTransform::new(self.walnut_key.clone());
-        self.program.visit_mut_with(&mut w_trans);
+        let mut program = match self.parse() {
+            Ok(program) => program,
+            Err(mut errors) => {
+                self.output_code = Some(self.input_code.clone());
+                self.diagnostics.append(&mut errors);
+                return;
+            }
+        };
+        let builtin_values = GLOBALS.set(&Globals::new(), || self.run_passes(&mut program));
+        self.program = Some(program);
+
+        // Filling these in prints the program, so it has to be in place first.
+        if !builtin_values.is_empty() {
+            self.satisfy_resolvers(builtin_values);
+        }
+    }
+
+    /*
+        Runs every pass over the program. Returns the values of resolvers that were settled at
+        build time, by label, for run() to fill in.
+    */
+    fn run_passes(&mut self, program: &mut Program) -> Vec<(String, String)> {
+        // Give every binding its own syntax context, intrinsics are matched by binding rather than name.
+        program.visit_mut_with(&mut resolver(Mark::new(), Mark::new(), true));
+
+        // Resolvers declared in this file.
+        let local_resolvers = scan_module_labels(program, &self.options.packages());
 
-        if w_trans.resolver_ids.len() > 0 {
-            self.need_resolver = true;
+        let packages = self.options.packages();
+        let re_exports = WalnutReExports::new(&self.entry_id, &packages, &self.options.resolver_config());
+        let mut scan_first = ScanFirst::new(packages, self.options.jsx_names(), re_exports);
+        program.visit_with(&mut scan_first);
+        self.walnut_sources = scan_first.sources.clone();
+
+        if !scan_first.should_run {
+            self.output_code = Some(self.input_code.clone());
+            return Vec::new();
         }
 
+        // Transform pass
+        let mut w_trans = WalnutTransform::new(
+            self.walnut_key.clone(),
+            scan_first.bindings.clone(),
+            ModuleStatics::collect(program),
+            self.options.jsx_names()
+        );
+        program.visit_mut_with(&mut w_trans);
+        self.diagnostics.append(&mut w_trans.diagnostics);
+
         // Final pass for cleanup and stuff
         let mut resolver_hash_set: HashSet<String> = HashSet::new();
+        // Local resolvers by binding, so a nested variable of the same name is left alone.
+        let mut local_resolver_ids: HashSet<Id> = HashSet::new();
         // Loop over the returned vec to generate a hash map
-        for id in w_trans.resolver_ids.iter() {
-            resolver_hash_set.insert(id.clone());
+        for (binding, _) in w_trans.resolver_ids.iter() {
+            if local_resolvers.declared.contains(binding) {
+                local_resolver_ids.insert(binding.clone());
+            } else if local_resolvers.imported.contains(binding) {
+                resolver_hash_set.insert(String::from(&*binding.0));
+            }
         }
 
-        let mut w_finalize = WalnutFinalize::new(resolver_hash_set);
-        self.program.visit_mut_with(&mut w_finalize);
+        // A local resolver declaration can go once $Resolve was its only user.
+        let mut resolver_refs = BindingRefs::new(local_resolver_ids.clone());
+        program.visit_with(&mut resolver_refs);
+
+        let local_resolvers_to_remove: HashSet<Id> = local_resolver_ids
+            .iter()
+            .filter(|binding| resolver_refs.spans(binding).is_empty() && !local_resolvers.is_exported(&binding.0))
+            .cloned()
+            .collect();
+
+        let mut w_finalize = WalnutFinalize::new(resolver_hash_set, local_resolvers_to_remove);
+        program.visit_mut_with(&mut w_finalize);
+
+        // Walnut imports only go once nothing refers to them, an intrinsic left behind keeps its import.
+        let mut binding_refs = BindingRefs::new(scan_first.removable.clone());
+        program.visit_with(&mut binding_refs);
+
+        let mut unused_walnut: HashSet<Id> = HashSet::new();
+        for id in scan_first.removable.iter() {
+            let spans = binding_refs.spans(id);
+            if spans.is_empty() {
+                unused_walnut.insert(id.clone());
+                continue;
+            }
+            // A namespace may still be used for makeResolver and such.
+            if matches!(scan_first.bindings.get(id), None | Some(Intrinsic::Namespace)) {
+                continue;
+            }
+            for span in spans {
+                self.diagnostics.push(
+                    Diagnostic::error(format!("`{}` is used here in a way walnut can't transform", id.0), *span)
+                );
+            }
+        }
 
-        if w_finalize.resolver_locs.len() > 0 {
-            let resolved_labels = try_resolve_resolver_label(
-                w_finalize.resolver_locs,
-                &self.entry_id
-            );
+        let mut remover = UnusedWalnutRemover::new(unused_walnut, self.options.packages());
+        program.visit_mut_with(&mut remover);
 
-            // We do this to call any resolver that dynamically returns a result.
-            for id in w_trans.resolver_ids.iter() {
-                let Some(v) = resolved_labels.get(id) else {
-                    return;
+        if !self.evaluate_resolvers {
+            return Vec::new();
+        }
+
+        let mut resolved_labels: HashMap<Id, ResolvedLabel> = HashMap::new();
+        for binding in local_resolver_ids.iter() {
+            if let Some(resolved) = local_resolvers.resolved(&binding.0) {
+                resolved_labels.insert(binding.clone(), resolved);
+            }
+        }
+
+        // Imported resolvers come back by local name, imports are top level so the name is the binding.
+        let imported_ids: HashMap<String, Id> = w_trans.resolver_ids
+            .iter()
+            .filter(|(binding, _)| local_resolvers.imported.contains(binding))
+            .map(|(binding, _)| (String::from(&*binding.0), binding.clone()))
+            .collect();
+        let mut failures = HashMap::new();
+        if !w_finalize.resolver_locs.is_empty() {
+            for (id, res) in try_resolve_resolver_label(
+                w_finalize.resolver_locs,
+                &self.entry_id,
+                &self.options.packages(),
+                &self.options.resolver_config()
+            ) {
+                let Some(binding) = imported_ids.get(&id) else {
+                    continue;
                 };
-                self.resolver_labels.push(v.clone());
+                match res {
+                    Ok(label) => {
+                        resolved_labels.insert(binding.clone(), label);
+                    }
+                    Err(failure) => {
+                        failures.insert(binding.clone(), failure);
+                    }
+                }
+            }
+        }
+
+        // We do this to call any resolver that dynamically returns a result.
+        // Pure resolver bodies and builtins are satisfied right here, anything unresolved is reported
+        // instead, so the caller never gets raw markers back silently.
+        let mut builtin_values: Vec<(String, String)> = Vec::new();
+        let active_keys = active_keys(&self.walnut_key);
+        let primary_key = active_keys.first().map_or("", |k| k.as_str());
+        for (binding, span) in w_trans.resolver_ids.iter() {
+            let id = &String::from(&*binding.0);
+            if let Some(resolved) = resolved_labels.get(binding) {
+                let v = &resolved.label;
                 self.label_map.insert(v.clone(), id.to_owned());
+
+                if let Some(value) = resolved.pure.as_ref().and_then(|p| p.eval(primary_key)) {
+                    builtin_values.push((v.clone(), value.to_js()));
+                    continue;
+                }
+
+                // Relative to wherever the resolver is declared, not every file using it.
+                let declared_in = resolved.module.as_deref().unwrap_or(&self.entry_id);
+                match resolve_builtin(v, declared_in) {
+                    Some(Ok(value)) => builtin_values.push((v.clone(), value)),
+                    Some(Err(e)) => {
+                        self.diagnostics.push(
+                            Diagnostic::error(format!("Builtin resolver `{v}` failed: {e}"), *span)
+                        );
+                    }
+                    None => self.resolver_labels.push(v.clone()),
+                }
+                continue;
             }
+
+            let message = match failures.get(binding) {
+                Some(failure) => {
+                    let resolved_to = match &failure.path {
+                        Some(path) => format!(" (resolved to {path})"),
+                        None => String::new(),
+                    };
+                    format!(
+                        "Unable to resolve `{id}` imported from \"{}\"{resolved_to}: {}",
+                        failure.specifier,
+                        failure.error
+                    )
+                }
+                None => format!("`{id}` is not a resolver imported or declared in this file"),
+            };
+            self.diagnostics.push(Diagnostic::error(message, *span));
         }
+
+        self.need_resolver = !self.resolver_labels.is_empty();
+        builtin_values
+    }
+
+    #[napi]
+    pub fn get_diagnostics(&self) -> Vec<WalnutDiagnostic> {
+        self.diagnostics
+            .iter()
+            .map(|d| d.to_js(&self.compiler.cm, &self.entry_id))
+            .collect()
+    }
+
+    // The import sources walnut was found under, as written in the file.
+    #[napi]
+    pub fn get_walnut_sources(&self) -> Vec<String> {
+        self.walnut_sources.clone()
     }
 
     #[napi]
     pub fn get_output(&mut self) -> String {
-        if self.output_code == None {
-            let printed_code = self.compiler.print(&self.program, PrintArgs {
+        if self.output_code.is_none() {
+            let Some(program) = &self.program else {
+                return self.input_code.clone();
+            };
+            let printed_code = self.compiler.print(program, PrintArgs {
                 source_root: None,
                 source_file_name: None,
                 output_path: None,
@@ -480,9 +1239,495 @@ impl WalnutHandler {
             };
 
             let mark = format!("/* __wres_{fn_name} */");
-            code = code.replacen(&*mark, &*value, 1);
+            code = code.replacen(&*mark, &value, 1);
         }
 
         self.output_code = Some(code);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+
+    const RES: &str = r#"import { makeResolver } from "walnut-ts";
+export const theme = makeResolver("theme-label");
+"#;
+
+    fn transform(fixture: &Fixture, file: &str, walnut_key: &str) -> WalnutHandler {
+        let path = fixture.path(file);
+        let code = std::fs::read_to_string(&path).unwrap();
+        let mut handler = WalnutHandler::new(code, path, String::from(walnut_key), WalnutOptions::default());
+        handler.run();
+        handler
+    }
+
+    fn messages(handler: &WalnutHandler) -> Vec<String> {
+        handler
+            .get_diagnostics()
+            .into_iter()
+            .map(|d| d.message)
+            .collect()
+    }
+
+    #[test]
+    fn imported_resolver_is_not_confused_with_a_nested_one() {
+        let fixture = Fixture::new(
+            "transform-nested-resolver",
+            &[
+                ("res.ts", RES),
+                (
+                    "main.ts",
+                    r#"import { $Resolve, makeResolver } from "walnut-ts";
+import { theme } from "./res";
+
+export function local() {
+    const theme = makeResolver("nested");
+    return theme;
+}
+
+export const value = $Resolve(theme);
+"#,
+                ),
+            ]
+        );
+        let mut handler = transform(&fixture, "main.ts", "ios");
+
+        assert_eq!(messages(&handler), Vec::<String>::new());
+        assert_eq!(handler.get_resolver_labels(), ["theme-label"]);
+        let output = handler.get_output();
+        assert!(!output.contains("./res"), "{output}");
+        assert!(output.contains("makeResolver(\"nested\")"), "{output}");
+        assert!(output.contains("/* __wres_theme */"), "{output}");
+    }
+
+    #[test]
+    fn same_file_resolver_is_matched_by_binding() {
+        let fixture = Fixture::new(
+            "transform-local-resolver",
+            &[
+                (
+                    "main.ts",
+                    r#"import { $Resolve, makeResolver } from "walnut-ts";
+
+const theme = makeResolver("top");
+
+export function pick(theme: unknown) {
+    const picked = $Resolve(theme);
+    return picked;
+}
+
+export const value = $Resolve(theme);
+"#,
+                ),
+            ]
+        );
+        let mut handler = transform(&fixture, "main.ts", "ios");
+
+        assert_eq!(messages(&handler), ["`theme` is not a resolver imported or declared in this file"]);
+        assert_eq!(handler.get_resolver_labels(), ["top"]);
+        // The top-level resolver was only there for its own $Resolve.
+        let output = handler.get_output();
+        assert!(!output.contains("makeResolver(\"top\")"), "{output}");
+    }
+
+    #[test]
+    fn same_file_resolver_only_used_by_resolve_is_removed() {
+        let fixture = Fixture::new(
+            "transform-unused-resolver",
+            &[
+                (
+                    "main.ts",
+                    r#"import { $Resolve, makeResolver } from "walnut-ts";
+
+const theme = makeResolver("top");
+
+export const value = $Resolve(theme);
+"#,
+                ),
+            ]
+        );
+        let mut handler = transform(&fixture, "main.ts", "ios");
+
+        assert_eq!(messages(&handler), Vec::<String>::new());
+        assert_eq!(handler.get_resolver_labels(), ["top"]);
+        let output = handler.get_output();
+        assert!(!output.contains("makeResolver"), "{output}");
+        assert!(!output.contains("walnut-ts"), "{output}");
+    }
+
+    #[test]
+    fn val_and_resolvers_agree_on_the_primary_key() {
+        let fixture = Fixture::new(
+            "transform-key-priority",
+            &[
+                (
+                    "main.ts",
+                    r#"import { $Resolve, $Val, makeResolver } from "walnut-ts";
+
+const current = makeResolver("current", (key) => key);
+
+export const size = $Val({ b: "from b", a: "from a" });
+export const fallback = $Val({ c: "from c", d: "from d" });
+export const resolved = $Resolve(current);
+"#,
+                ),
+            ]
+        );
+        let mut handler = transform(&fixture, "main.ts", "a, b");
+
+        assert_eq!(messages(&handler), Vec::<String>::new());
+        let output = handler.get_output();
+        assert!(output.contains(r#"size = "from a""#), "{output}");
+        assert!(output.contains(r#"fallback = "from c""#), "{output}");
+        assert!(output.contains(r#"resolved = "a""#), "{output}");
+    }
+
+    #[test]
+    fn val_reads_shorthand_entries_and_keeps_spreads() {
+        let fixture = Fixture::new(
+            "transform-val-entries",
+            &[
+                (
+                    "main.ts",
+                    r#"import { $Val } from "walnut-ts";
+
+const ios = "from ios";
+const base = { web: "from web" };
+
+export const short = $Val({ ios, web: "from web" });
+export const spread = $Val({ ...base, ios: "from ios" });
+"#,
+                ),
+            ]
+        );
+        let mut handler = transform(&fixture, "main.ts", "ios");
+
+        assert_eq!(
+            messages(&handler),
+            [
+                "Spreads aren't supported in $Val objects, list every key",
+                "`$Val` is used here in a way walnut can't transform",
+            ]
+        );
+        let output = handler.get_output();
+        assert!(output.contains("short = ios;"), "{output}");
+        // The object it can't read stays as written, along with its import.
+        assert!(output.contains("spread = $Val({"), "{output}");
+        assert!(output.contains(r#"from "walnut-ts""#), "{output}");
+    }
+
+    #[test]
+    fn syntax_errors_are_reported_and_leave_the_file_as_is() {
+        let code = "import { $Val } from \"walnut-ts\";\nexport const size = $Val({ ios: 1 ;\n";
+        let mut handler = WalnutHandler::new(String::from(code), String::from("broken.ts"), String::from("ios"), WalnutOptions::default());
+        handler.run();
+
+        let diagnostics = handler.get_diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, "error");
+        assert!(diagnostics[0].message.starts_with("Syntax error: "), "{}", diagnostics[0].message);
+        assert_eq!(diagnostics[0].line, 2);
+        assert_eq!(handler.get_output(), code);
+    }
+
+    #[test]
+    fn walnut_requires_are_matched_by_package() {
+        let fixture = Fixture::new(
+            "transform-require",
+            &[
+                (
+                    "main.js",
+                    r#"const { $Val } = require("walnut-ts/runtime");
+const other = require("walnut-tsx");
+const size = $Val({ ios: 1, web: 2 });
+module.exports = { size, other };
+"#,
+                ),
+            ]
+        );
+        let mut handler = transform(&fixture, "main.js", "ios");
+
+        assert_eq!(messages(&handler), Vec::<String>::new());
+        assert_eq!(handler.get_walnut_sources(), ["walnut-ts/runtime"]);
+        let output = handler.get_output();
+        assert!(output.contains("size = 1"), "{output}");
+        assert!(!output.contains("walnut-ts/runtime"), "{output}");
+        assert!(output.contains(r#"require("walnut-tsx")"#), "{output}");
+    }
+
+    #[test]
+    fn resolvers_are_followed_through_re_exports() {
+        let fixture = Fixture::new(
+            "transform-barrels",
+            &[
+                ("res/icons.ts", "import { makeResolver } from \"walnut-ts\";\nexport const iconResolver = makeResolver(\"icons\");\n"),
+                ("res/theme.ts", "import { makeResolver } from \"walnut-ts\";\nexport const theme = makeResolver(\"theme\");\n"),
+                ("res/fonts.ts", "import { makeResolver } from \"walnut-ts\";\nexport const fonts = makeResolver(\"fonts\");\n"),
+                (
+                    "res/index.ts",
+                    "export { iconResolver as icons } from \"./icons\";\nexport * from \"./theme\";\nimport { fonts } from \"./fonts\";\nexport { fonts as typefaces };\n",
+                ),
+                (
+                    "main.ts",
+                    r#"import { $Resolve } from "walnut-ts";
+import { icons, theme, typefaces } from "./res";
+
+export const a = $Resolve(icons);
+export const b = $Resolve(theme);
+export const c = $Resolve(typefaces);
+"#,
+                ),
+            ]
+        );
+        let mut handler = transform(&fixture, "main.ts", "ios");
+
+        assert_eq!(messages(&handler), Vec::<String>::new());
+        let mut labels = handler.get_resolver_labels();
+        labels.sort();
+        assert_eq!(labels, ["fonts", "icons", "theme"]);
+        assert!(handler.need_resolver);
+    }
+
+    #[test]
+    fn re_export_cycles_are_reported() {
+        let fixture = Fixture::new(
+            "transform-re-export-cycle",
+            &[
+                ("a.ts", "export { theme } from \"./b\";\n"),
+                ("b.ts", "export { theme } from \"./a\";\n"),
+                (
+                    "main.ts",
+                    r#"import { $Resolve } from "walnut-ts";
+import { theme } from "./a";
+
+export const value = $Resolve(theme);
+"#,
+                ),
+            ]
+        );
+        let handler = transform(&fixture, "main.ts", "ios");
+
+        let messages = messages(&handler);
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("re-export cycle while looking for `theme`"), "{}", messages[0]);
+    }
+
+    #[test]
+    fn builtins_read_relative_to_the_declaring_module() {
+        let fixture = Fixture::new(
+            "transform-builtins",
+            &[
+                ("shared/motd.txt", "hello"),
+                ("shared/config.json", "{ \"retries\": 3 }"),
+                (
+                    "shared/res.ts",
+                    r#"import { makeResolver } from "walnut-ts";
+export const motd = makeResolver("file:motd.txt");
+export const config = makeResolver("json:config.json");
+export const unset = makeResolver("env:WALNUT_TEST_SURELY_UNSET");
+"#,
+                ),
+                (
+                    "app/main.ts",
+                    r#"import { $Resolve } from "walnut-ts";
+import { config, motd, unset } from "../shared/res";
+
+export const a = $Resolve(motd);
+export const b = $Resolve(config);
+export const c = $Resolve(unset);
+"#,
+                ),
+            ]
+        );
+        let mut handler = transform(&fixture, "app/main.ts", "ios");
+
+        assert_eq!(messages(&handler), Vec::<String>::new());
+        assert!(!handler.need_resolver);
+        let output = handler.get_output();
+        assert!(output.contains(r#"a = "hello""#), "{output}");
+        assert!(output.contains("retries"), "{output}");
+        assert!(output.contains("c = undefined"), "{output}");
+        assert!(!output.contains("walnut-ts"), "{output}");
+    }
+
+    #[test]
+    fn else_branches_follow_their_group() {
+        let fixture = Fixture::new(
+            "transform-else",
+            &[
+                (
+                    "main.tsx",
+                    r#"import { $Walnut } from "walnut-ts";
+
+export const a = (
+    <div>
+        <$Walnut key="ios"><b>ios</b></$Walnut>
+        <$Walnut key="web"><b>web</b></$Walnut>
+        <$Walnut.Else><b>other</b></$Walnut.Else>
+    </div>
+);
+"#,
+                ),
+            ]
+        );
+
+        let mut ios = transform(&fixture, "main.tsx", "ios");
+        assert_eq!(messages(&ios), Vec::<String>::new());
+        let output = ios.get_output();
+        assert!(output.contains("<b>ios</b>") && !output.contains("web") && !output.contains("other"), "{output}");
+
+        let mut android = transform(&fixture, "main.tsx", "android");
+        let output = android.get_output();
+        assert!(output.contains("<b>other</b>") && !output.contains("<b>ios</b>"), "{output}");
+        assert!(!output.contains("walnut-ts"), "{output}");
+    }
+
+    #[test]
+    fn switch_keeps_the_first_matching_case() {
+        let fixture = Fixture::new(
+            "transform-switch",
+            &[
+                (
+                    "main.tsx",
+                    r#"import { $Walnut } from "walnut-ts";
+
+export const a = (
+    <div>
+        <$Walnut.Switch>
+            <$Walnut when="ios && tablet"><b>ipad</b></$Walnut>
+            <$Walnut key="ios"><b>iphone</b></$Walnut>
+            <$Walnut.Else><b>other</b></$Walnut.Else>
+        </$Walnut.Switch>
+    </div>
+);
+"#,
+                ),
+            ]
+        );
+
+        let mut tablet = transform(&fixture, "main.tsx", "ios, tablet");
+        assert_eq!(messages(&tablet), ["More than one case matches in <$Walnut.Switch>, keeping the first"]);
+        let output = tablet.get_output();
+        assert!(output.contains("<b>ipad</b>") && !output.contains("iphone") && !output.contains("other"), "{output}");
+
+        let mut web = transform(&fixture, "main.tsx", "web");
+        assert_eq!(messages(&web), Vec::<String>::new());
+        let output = web.get_output();
+        assert!(output.contains("<b>other</b>") && !output.contains("ipad"), "{output}");
+    }
+
+    #[test]
+    fn root_elements_are_replaced_by_their_children() {
+        let fixture = Fixture::new(
+            "transform-root",
+            &[
+                (
+                    "main.tsx",
+                    r#"import { $Walnut } from "walnut-ts";
+
+export function One() {
+    return <$Walnut key="ios"><b>ios</b></$Walnut>;
+}
+
+export function Many() {
+    return (
+        <$Walnut key="ios">
+            <b>first</b>
+            <b>second</b>
+        </$Walnut>
+    );
+}
+
+export const list = [1, 2].map((n) => <$Walnut key="web"><i>{n}</i></$Walnut>);
+export const fragment = <><$Walnut key="ios"><u>kept</u></$Walnut></>;
+"#,
+                ),
+            ]
+        );
+        let mut handler = transform(&fixture, "main.tsx", "ios");
+
+        assert_eq!(messages(&handler), Vec::<String>::new());
+        let output = handler.get_output();
+        assert!(output.contains("return <b>ios</b>;"), "{output}");
+        assert!(output.contains("<>"), "{output}");
+        assert!(output.contains("<b>first</b>") && output.contains("<b>second</b>"), "{output}");
+        assert!(output.contains("(n)=>null"), "{output}");
+        assert!(output.contains("<u>kept</u>"), "{output}");
+        assert!(!output.contains("$Walnut"), "{output}");
+    }
+
+    #[test]
+    fn variant_props_override_explicit_ones() {
+        let fixture = Fixture::new(
+            "transform-variant-props",
+            &[
+                (
+                    "main.tsx",
+                    r#"export const a = <Button size="sm" walnut:ios={{ size: "lg", haptic: true }} walnut:web={{ size: "md" }} />;
+export const b = <Button $props={{ ios: { tone: "dark" }, web: { tone: "light" } }} />;
+"#,
+                ),
+            ]
+        );
+        let mut handler = transform(&fixture, "main.tsx", "ios");
+
+        assert_eq!(messages(&handler), Vec::<String>::new());
+        let output = handler.get_output();
+        assert!(output.contains(r#"<Button size="lg" haptic={true}/>"#), "{output}");
+        assert!(output.contains(r#"<Button tone="dark"/>"#), "{output}");
+    }
+
+    #[test]
+    fn aliases_are_transformed_and_shadowing_names_are_not() {
+        let fixture = Fixture::new(
+            "transform-bindings",
+            &[
+                (
+                    "main.ts",
+                    r#"import { $Val as pick } from "walnut-ts";
+
+export const size = pick({ ios: 1, web: 2 });
+
+export function local($Val: (o: object) => number) {
+    const inner = $Val({ ios: 3, web: 4 });
+    return inner;
+}
+"#,
+                ),
+            ]
+        );
+        let mut handler = transform(&fixture, "main.ts", "ios");
+
+        assert_eq!(messages(&handler), Vec::<String>::new());
+        let output = handler.get_output();
+        assert!(output.contains("size = 1"), "{output}");
+        assert!(output.contains("inner = $Val({"), "{output}");
+        assert!(!output.contains("walnut-ts"), "{output}");
+    }
+
+    #[test]
+    fn imports_stay_while_a_usage_is_left_untransformed() {
+        let fixture = Fixture::new(
+            "transform-deferred-removal",
+            &[
+                (
+                    "main.ts",
+                    r#"import { $Resolve, $Val } from "walnut-ts";
+
+export const size = $Val({ ios: 1, web: 2 });
+console.log($Val);
+"#,
+                ),
+            ]
+        );
+        let mut handler = transform(&fixture, "main.ts", "ios");
+
+        assert_eq!(messages(&handler), ["`$Val` is used here in a way walnut can't transform"]);
+        let output = handler.get_output();
+        assert!(output.contains("size = 1"), "{output}");
+        // Only what's still referred to is kept.
+        assert!(output.contains(r#"import { $Val } from "walnut-ts""#), "{output}");
+    }
+}
@@ -0,0 +1,503 @@
+#![deny(clippy::all)]
+
+use std::collections::{ BTreeMap, BTreeSet, HashMap };
+use swc_common::{ sync::Lrc, FileName, Globals, Mark, SourceMap, Span, GLOBALS };
+use swc_ecma_ast::*;
+use swc_ecma_transforms_base::resolver;
+use swc_ecma_visit::{ Visit, VisitMutWith, VisitWith };
+
+use crate::diagnostics::WalnutDiagnostic;
+use crate::helpers::ObjectLitFinder;
+use crate::options::WalnutOptions;
+use crate::resolve_modules::{ parse_file_in, resolve_deps, DependencyOptions };
+use crate::resolver::WalnutReExports;
+use crate::scan_first::ScanFirst;
+use crate::static_eval::ModuleStatics;
+use crate::transform::{ intrinsic_of, Intrinsic, WalnutJsx, WalnutTransform };
+
+/*
+    One walnut construct and the keys it mentions.
+        kind        val, pval, walnut (a <$Walnut> gate), switch or resolve
+        keys        for a switch, the keys of all its cases. $Resolve leaves the key to the
+                    resolver, so it has none
+        fallback    the construct covers keys it doesn't list: a switch with <$Walnut.Else>,
+                    or a $Resolve
+        resolver    the resolver a $Resolve call names
+*/
+#[napi(object)]
+#[derive(Clone, Debug)]
+pub struct WalnutUsage {
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+    pub kind: String,
+    pub keys: Vec<String>,
+    pub fallback: bool,
+    pub resolver: Option<String>,
+}
+
+impl WalnutUsage {
+    // Whether a build for `key` has to fall back on something for this construct. $Val takes
+    // its first value then and a switch renders nothing, a lone gate is simply left out.
+    fn lacks(&self, key: &str) -> bool {
+        matches!(self.kind.as_str(), "val" | "pval" | "switch") && !self.fallback && !self.keys.iter().any(|k| k == key)
+    }
+}
+
+#[napi]
+pub struct UsageIndex {
+    usages: Vec<WalnutUsage>,
+    // Files that couldn't be indexed, and constructs whose keys couldn't be read.
+    errors: Vec<WalnutDiagnostic>,
+}
+
+#[napi]
+impl UsageIndex {
+    #[napi]
+    pub fn get_usages(&self) -> Vec<WalnutUsage> {
+        self.usages.clone()
+    }
+
+    // What's missing from the index: files that didn't parse and constructs with keys that
+    // aren't known at build time.
+    #[napi]
+    pub fn get_errors(&self) -> Vec<WalnutDiagnostic> {
+        self.errors.clone()
+    }
+
+    #[napi]
+    pub fn get_keys_by_file(&self) -> HashMap<String, Vec<String>> {
+        self.keys_by_file().into_iter().collect()
+    }
+
+    #[napi]
+    pub fn get_files_by_key(&self) -> HashMap<String, Vec<String>> {
+        self.files_by_key().into_iter().collect()
+    }
+
+    fn keys_by_file(&self) -> BTreeMap<String, Vec<String>> {
+        let mut by_file: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for usage in self.usages.iter() {
+            by_file.entry(usage.file.clone()).or_default().extend(usage.keys.iter().cloned());
+        }
+        by_file
+            .into_iter()
+            .map(|(file, keys)| (file, keys.into_iter().collect()))
+            .collect()
+    }
+
+    fn files_by_key(&self) -> BTreeMap<String, Vec<String>> {
+        let mut by_key: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for usage in self.usages.iter() {
+            for key in usage.keys.iter() {
+                by_key.entry(key.clone()).or_default().insert(usage.file.clone());
+            }
+        }
+        by_key
+            .into_iter()
+            .map(|(key, files)| (key, files.into_iter().collect()))
+            .collect()
+    }
+
+    /*
+        The $Val/$PVal objects and switches without a value for `key`.
+    */
+    #[napi]
+    pub fn lacking_key(&self, key: String) -> Vec<WalnutUsage> {
+        self.usages
+            .iter()
+            .filter(|u| u.lacks(&key))
+            .cloned()
+            .collect()
+    }
+
+    /*
+        Keys mentioned by a single construct in the whole project, often a typo of another key.
+    */
+    #[napi]
+    pub fn get_single_use_keys(&self) -> Vec<String> {
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for usage in self.usages.iter() {
+            for key in usage.keys.iter() {
+                *counts.entry(key).or_insert(0) += 1;
+            }
+        }
+        counts
+            .into_iter()
+            .filter(|(_, count)| *count == 1)
+            .map(|(key, _)| String::from(key))
+            .collect()
+    }
+
+    /*
+        The whole report: keys per file, files per key, constructs lacking each known key,
+        single use keys and everything that couldn't be indexed.
+    */
+    #[napi]
+    pub fn to_json(&self) -> String {
+        let files_by_key = self.files_by_key();
+        let lacking: BTreeMap<&String, Vec<String>> = files_by_key
+            .keys()
+            .map(|key| {
+                let sites = self.usages
+                    .iter()
+                    .filter(|u| u.lacks(key))
+                    .map(|u| format!("{}:{}:{}", u.file, u.line, u.column))
+                    .collect();
+                (key, sites)
+            })
+            .collect();
+        let errors: Vec<serde_json::Value> = self.errors
+            .iter()
+            .map(|d| {
+                serde_json::json!({
+                    "file": d.file,
+                    "line": d.line,
+                    "column": d.column,
+                    "message": d.message,
+                })
+            })
+            .collect();
+
+        serde_json::to_string_pretty(
+            &serde_json::json!({
+                "keysByFile": self.keys_by_file(),
+                "filesByKey": files_by_key,
+                "lackingKey": lacking,
+                "singleUseKeys": self.get_single_use_keys(),
+                "errors": errors,
+            })
+        ).unwrap_or_default()
+    }
+}
+
+/*
+    Indexes the walnut constructs of every module reachable from `entries`, going by the same
+    graph resolve_deps builds.
+*/
+pub(crate) fn index_usages(
+    base: &String,
+    entries: &[String],
+    options: &WalnutOptions,
+    graph_options: &DependencyOptions
+) -> UsageIndex {
+    let untransformed = DependencyOptions { walnut_key: None, ..graph_options.clone() };
+    let graph = resolve_deps(base, entries, options, &untransformed);
+
+    let mut index = UsageIndex { usages: Vec::new(), errors: Vec::new() };
+    for node in graph.get_nodes().iter().filter(|n| n.contains_walnut) {
+        match index_file(&node.path, options) {
+            Ok((mut usages, mut errors)) => {
+                index.usages.append(&mut usages);
+                index.errors.append(&mut errors);
+            }
+            Err(e) => {
+                index.errors.push(WalnutDiagnostic {
+                    severity: String::from("error"),
+                    message: e,
+                    file: node.path.clone(),
+                    line: 0,
+                    column: 0,
+                });
+            }
+        }
+    }
+    index
+}
+
+fn index_file(path: &str, options: &WalnutOptions) -> Result<(Vec<WalnutUsage>, Vec<WalnutDiagnostic>), String> {
+    let cm = Lrc::<SourceMap>::default();
+    let mut program = parse_file_in(&cm, &FileName::Real(path.into()))?;
+
+    let (spans, diagnostics) = GLOBALS.set(&Globals::new(), || {
+        // Same binding resolution as the transform, so shadowed names aren't counted.
+        program.visit_mut_with(&mut resolver(Mark::new(), Mark::new(), true));
+
+        let packages = options.packages();
+        let re_exports = WalnutReExports::new(path, &packages, &options.resolver_config());
+        let mut scan_first = ScanFirst::new(packages, options.jsx_names(), re_exports);
+        program.visit_with(&mut scan_first);
+
+        let mut collector = UsageCollector {
+            transform: WalnutTransform::new(
+                String::new(),
+                scan_first.bindings,
+                ModuleStatics::collect(&program),
+                options.jsx_names()
+            ),
+            usages: Vec::new(),
+        };
+        program.visit_with(&mut collector);
+        (collector.usages, collector.transform.diagnostics)
+    });
+    let errors: Vec<WalnutDiagnostic> = diagnostics
+        .iter()
+        .map(|d| d.to_js(&cm, path))
+        .collect();
+
+    let usages = spans
+        .into_iter()
+        .map(|(span, usage)| {
+            let loc = cm.lookup_char_pos(span.lo);
+            WalnutUsage {
+                file: String::from(path),
+                line: loc.line as u32,
+                column: (loc.col.0 as u32) + 1,
+                ..usage
+            }
+        })
+        .collect();
+
+    Ok((usages, errors))
+}
+
+/*
+    Finds walnut constructs and reads their keys the way WalnutTransform does, positions are
+    filled in afterwards.
+*/
+struct UsageCollector {
+    transform: WalnutTransform,
+    usages: Vec<(Span, WalnutUsage)>,
+}
+
+impl UsageCollector {
+    fn push(&mut self, span: Span, kind: &str, keys: Vec<String>, fallback: bool, resolver: Option<String>) {
+        let mut unique: Vec<String> = Vec::new();
+        for key in keys {
+            if !unique.contains(&key) {
+                unique.push(key);
+            }
+        }
+
+        self.usages.push((
+            span,
+            WalnutUsage {
+                file: String::new(),
+                line: 0,
+                column: 0,
+                kind: String::from(kind),
+                keys: unique,
+                fallback,
+                resolver,
+            },
+        ));
+    }
+
+    // The keys every condition attribute of a gate mentions.
+    fn gate_keys(&mut self, element: &JSXElement) -> Vec<String> {
+        let mut keys = Vec::new();
+        for attr in element.opening.attrs.iter() {
+            let JSXAttrOrSpread::JSXAttr(attr) = attr else {
+                continue;
+            };
+            let JSXAttrName::Ident(name) = &attr.name else {
+                continue;
+            };
+
+            match &*name.sym {
+                "key" | "any" | "all" | "not" => {
+                    keys.extend(self.transform.attr_keys(&name.sym, attr).unwrap_or_default());
+                }
+                "when" => {
+                    keys.extend(self.transform.attr_condition(attr).map(|c| c.keys()).unwrap_or_default());
+                }
+                _ => {}
+            }
+        }
+        keys
+    }
+}
+
+impl Visit for UsageCollector {
+    fn visit_call_expr(&mut self, n: &CallExpr) {
+        let intrinsic = match &n.callee {
+            Callee::Expr(callee) => intrinsic_of(&self.transform.bindings, callee),
+            _ => None,
+        };
+
+        match intrinsic {
+            Some(kind @ (Intrinsic::Val | Intrinsic::PVal)) => {
+                // The same object the transform picks a value from.
+                let mut finder = ObjectLitFinder::new();
+                n.visit_with(&mut finder);
+                let keys = finder.res.map(|obj| self.transform.val_keys(&obj)).unwrap_or_default();
+                let kind = if kind == Intrinsic::Val { "val" } else { "pval" };
+                self.push(n.span, kind, keys, false, None);
+            }
+            Some(Intrinsic::Resolve) => {
+                let resolver = match n.args.first().map(|a| &*a.expr) {
+                    Some(Expr::Ident(i)) => Some(String::from(&*i.sym)),
+                    _ => None,
+                };
+                self.push(n.span, "resolve", Vec::new(), true, resolver);
+            }
+            _ => {}
+        }
+
+        n.visit_children_with(self);
+    }
+
+    fn visit_jsx_element(&mut self, n: &JSXElement) {
+        match self.transform.walnut_jsx_kind(&n.opening.name) {
+            Some(WalnutJsx::Gate) if !self.transform.is_fallback(n) => {
+                let keys = self.gate_keys(n);
+                self.push(n.opening.span, "walnut", keys, false, None);
+            }
+            Some(WalnutJsx::Switch) => {
+                let mut keys = Vec::new();
+                let mut fallback = false;
+                for child in n.children.iter() {
+                    let JSXElementChild::JSXElement(case) = child else {
+                        continue;
+                    };
+                    match self.transform.walnut_jsx_kind(&case.opening.name) {
+                        Some(WalnutJsx::Gate) if !self.transform.is_fallback(case) => {
+                            keys.extend(self.gate_keys(case));
+                        }
+                        Some(WalnutJsx::Gate | WalnutJsx::Else) => {
+                            fallback = true;
+                        }
+                        _ => {}
+                    }
+                }
+                self.push(n.opening.span, "switch", keys, fallback, None);
+
+                // The cases are part of the switch, only what they render is indexed on its own.
+                n.opening.visit_with(self);
+                for child in n.children.iter() {
+                    match child {
+                        JSXElementChild::JSXElement(case)
+                            if matches!(self.transform.walnut_jsx_kind(&case.opening.name), Some(WalnutJsx::Gate | WalnutJsx::Else)) => {
+                            case.children.visit_with(self);
+                        }
+                        child => child.visit_with(self),
+                    }
+                }
+                return;
+            }
+            _ => {}
+        }
+
+        n.visit_children_with(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+
+    const SWITCH: &str = r#"import { $Walnut } from "walnut-ts";
+
+export const Banner = () => (
+    <$Walnut.Switch>
+        <$Walnut key="ios">
+            <span>ios</span>
+        </$Walnut>
+        <$Walnut key={["web", "desktop"]}>
+            <$Walnut key="dark">
+                <b>dark</b>
+            </$Walnut>
+        </$Walnut>
+        <$Walnut key={pickKey()}>
+            <i>dynamic</i>
+        </$Walnut>
+        <$Walnut.Else>
+            <span>other</span>
+        </$Walnut.Else>
+    </$Walnut.Switch>
+);
+"#;
+
+    fn index(fixture: &Fixture, file: &str) -> UsageIndex {
+        let (usages, errors) = index_file(&fixture.path(file), &WalnutOptions::default()).unwrap();
+        UsageIndex { usages, errors }
+    }
+
+    #[test]
+    fn indexes_switch_cases_once() {
+        let fixture = Fixture::new("usage-switch", &[("banner.tsx", SWITCH)]);
+        let index = index(&fixture, "banner.tsx");
+
+        let kinds: Vec<(&str, &[String], bool)> = index.usages
+            .iter()
+            .map(|u| (u.kind.as_str(), u.keys.as_slice(), u.fallback))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("switch", &[String::from("ios"), String::from("web"), String::from("desktop")][..], true),
+                ("walnut", &[String::from("dark")][..], false),
+            ]
+        );
+
+        assert_eq!(index.get_single_use_keys(), ["dark", "desktop", "ios", "web"]);
+        assert_eq!(index.files_by_key().get("ios"), Some(&Vec::from([fixture.path("banner.tsx")])));
+    }
+
+    #[test]
+    fn reports_unreadable_case_keys_once() {
+        let fixture = Fixture::new("usage-errors", &[("banner.tsx", SWITCH)]);
+        let errors = index(&fixture, "banner.tsx").get_errors();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].file, fixture.path("banner.tsx"));
+        assert_eq!(errors[0].line, 13);
+    }
+
+    #[test]
+    fn lacking_key_skips_constructs_with_a_fallback() {
+        let fixture = Fixture::new(
+            "usage-lacking",
+            &[
+                ("banner.tsx", SWITCH),
+                ("title.ts", "import { $Val } from \"walnut-ts\";\nexport const title = $Val({ ios: \"iOS\", web: \"Web\" });\n"),
+            ]
+        );
+        let mut project = index(&fixture, "banner.tsx");
+        project.usages.append(&mut index(&fixture, "title.ts").usages);
+
+        let lacking: Vec<String> = project
+            .lacking_key(String::from("desktop"))
+            .into_iter()
+            .map(|u| u.kind)
+            .collect();
+        assert_eq!(lacking, ["val"]);
+        assert!(project.lacking_key(String::from("ios")).is_empty());
+    }
+
+    #[test]
+    fn indexes_every_walnut_module_of_a_project() {
+        let fixture = Fixture::new(
+            "usage-project",
+            &[
+                ("main.ts", "import { title } from \"./title\";\nimport { theme } from \"./theme\";\nimport \"./plain\";\nexport const all = [title, theme];\n"),
+                ("title.ts", "import { $PVal } from \"walnut-ts\";\nexport const title = $PVal({ ios: \"iOS\", web: \"Web\" });\n"),
+                (
+                    "theme.ts",
+                    "import { $Resolve, makeResolver } from \"walnut-ts\";\nconst current = makeResolver(\"theme\");\nexport const theme = $Resolve(current);\n",
+                ),
+                ("plain.ts", "export const plain = 1;\n"),
+            ]
+        );
+        let base = fixture.root().to_string_lossy().into_owned();
+        let project = index_usages(&base, &[fixture.path("main.ts")], &WalnutOptions::default(), &DependencyOptions::default());
+
+        assert!(project.get_errors().is_empty());
+        let mut usages: Vec<(String, String, Vec<String>, Option<String>)> = project
+            .get_usages()
+            .into_iter()
+            .map(|u| (u.file, u.kind, u.keys, u.resolver))
+            .collect();
+        usages.sort();
+        assert_eq!(
+            usages,
+            [
+                (fixture.path("theme.ts"), String::from("resolve"), Vec::new(), Some(String::from("current"))),
+                (fixture.path("title.ts"), String::from("pval"), Vec::from([String::from("ios"), String::from("web")]), None),
+            ]
+        );
+        assert_eq!(project.keys_by_file().get(&fixture.path("title.ts")), Some(&Vec::from([String::from("ios"), String::from("web")])));
+        assert!(!project.keys_by_file().contains_key(&fixture.path("plain.ts")));
+    }
+}